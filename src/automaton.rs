@@ -0,0 +1,305 @@
+use std::collections::{HashMap, HashSet};
+
+pub type Point = (isize, isize);
+
+// which surrounding cells count as neighbours
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Neighborhood {
+  // orthogonal neighbours
+  VonNeumann,
+  // orthogonal and diagonal neighbours
+  Moore,
+  Custom(Vec<Point>)
+}
+
+impl Neighborhood {
+  pub fn offsets(&self) -> &[Point] {
+    match self {
+      Neighborhood::VonNeumann => &[(0, -1), (-1, 0), (1, 0), (0, 1)],
+      Neighborhood::Moore => &[(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)],
+      Neighborhood::Custom(offsets) => offsets
+    }
+  }
+}
+
+// a rule where cells fire and each firing charges its neighbours, possibly
+// making them fire in turn within the same step
+pub trait CascadeRule {
+  type Cell;
+
+  // add one unit of charge to a cell, returning true if it now fires
+  fn charge(&self, cell: &mut Self::Cell) -> bool;
+
+  // called once at the end of a step on every cell that fired
+  fn settle(&self, cell: &mut Self::Cell);
+}
+
+// what lies past the cells of the automaton
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edges<C> {
+  // nothing, neighbours off the grid are missing
+  Bounded,
+  // the `size` cells from `min` wrap around, leaving one side re-enters the opposite one
+  Wrapping { min: Point, size: Point },
+  // an infinite background of identical cells.  synchronous steps grow the
+  // grid by the reach of the neighbourhood and evolve the background too.
+  Background(C)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Automaton<C> {
+  cells: HashMap<Point, C>,
+  neighborhood: Neighborhood,
+  edges: Edges<C>
+}
+
+impl<C> Automaton<C> {
+  pub fn new(cells: HashMap<Point, C>, neighborhood: Neighborhood) -> Self {
+    Automaton { cells, neighborhood, edges: Edges::Bounded }
+  }
+
+  // wrap around the bounding box of the current cells
+  pub fn with_wrapping(mut self) -> Self {
+    let xs = self.cells.keys().map(|p| p.0);
+    let ys = self.cells.keys().map(|p| p.1);
+
+    if let (Some(x0), Some(x1), Some(y0), Some(y1)) = (xs.clone().min(), xs.max(), ys.clone().min(), ys.max()) {
+      self.edges = Edges::Wrapping { min: (x0, y0), size: (x1 - x0 + 1, y1 - y0 + 1) };
+    }
+    self
+  }
+
+  pub fn with_background(mut self, background: C) -> Self {
+    self.edges = Edges::Background(background);
+    self
+  }
+
+  pub fn background(&self) -> Option<&C> {
+    match &self.edges {
+      Edges::Background(cell) => Some(cell),
+      _ => None
+    }
+  }
+
+  pub fn cells(&self) -> &HashMap<Point, C> {
+    &self.cells
+  }
+
+  pub fn len(&self) -> usize {
+    self.cells.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.cells.is_empty()
+  }
+
+  // the cell `pos` refers to once wrapped, if there is one
+  fn resolve(&self, pos: Point) -> Option<Point> {
+    let pos = match self.edges {
+      Edges::Wrapping { min, size } => (
+        (pos.0 - min.0).rem_euclid(size.0) + min.0,
+        (pos.1 - min.1).rem_euclid(size.1) + min.1
+      ),
+      _ => pos
+    };
+
+    Some(pos).filter(|p| self.cells.contains_key(p))
+  }
+
+  // neighbours of `pos` that are part of the automaton
+  pub fn neighbors(&self, pos: Point) -> impl Iterator<Item=Point> + '_ {
+    self.neighborhood
+      .offsets()
+      .iter()
+      .filter_map(move |(dx, dy)| self.resolve((pos.0 + dx, pos.1 + dy)))
+  }
+
+  // the cell at each neighbourhood offset of `pos`, in offset order, falling
+  // back to the background.  None where there is neither.
+  pub fn window(&self, pos: Point) -> Vec<Option<&C>> {
+    self.neighborhood
+      .offsets()
+      .iter()
+      .map(|(dx, dy)| {
+        self.resolve((pos.0 + dx, pos.1 + dy))
+          .map(|p| &self.cells[&p])
+          .or(self.background())
+      })
+      .collect()
+  }
+
+  // every cell becomes `rule` applied to itself and its window, all at once
+  pub fn step_sync<F>(&mut self, rule: F)
+  where
+    C: Clone,
+    F: Fn(&C, &[Option<&C>]) -> C
+  {
+    let mut positions : HashSet<Point> = self.cells.keys().copied().collect();
+
+    // background cells that see a grid cell may change, so they join the grid
+    if let Edges::Background(_) = self.edges {
+      for &(x, y) in self.cells.keys() {
+        positions.extend(self.neighborhood.offsets().iter().map(|(dx, dy)| (x - dx, y - dy)));
+      }
+    }
+
+    let next = positions
+      .into_iter()
+      .map(|pos| {
+        let cell = self.cells.get(&pos).or(self.background()).unwrap();
+        (pos, rule(cell, &self.window(pos)))
+      })
+      .collect();
+
+    if let Edges::Background(cell) = &self.edges {
+      let around = vec![Some(cell); self.neighborhood.offsets().len()];
+      self.edges = Edges::Background(rule(cell, &around));
+    }
+    self.cells = next;
+  }
+
+  // charge every cell once, then cascade: each cell that fires charges its
+  // neighbours, worked off a stack so only fired cells are revisited.
+  // returns the cells that fired, after they have been settled.
+  pub fn step_cascade<R>(&mut self, rule: &R) -> HashSet<Point>
+  where
+    R: CascadeRule<Cell=C>
+  {
+    let mut fired : HashSet<Point> = HashSet::new();
+    let mut to_visit : Vec<Point> = vec![];
+
+    for (&pos, cell) in self.cells.iter_mut() {
+      if rule.charge(cell) {
+        fired.insert(pos);
+        to_visit.push(pos);
+      }
+    }
+
+    while let Some(pos) = to_visit.pop() {
+      let around : Vec<Point> = self.neighbors(pos).collect();
+
+      for np in around {
+        if fired.contains(&np) {
+          continue;
+        }
+        if rule.charge(self.cells.get_mut(&np).unwrap()) {
+          fired.insert(np);
+          to_visit.push(np);
+        }
+      }
+    }
+
+    for pos in &fired {
+      rule.settle(self.cells.get_mut(pos).unwrap());
+    }
+
+    fired
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_step_sync() {
+    // a blinker in conway's life flips between a row and a column
+    let cells : HashMap<Point, bool> = (0..5)
+      .flat_map(|y| (0..5).map(move |x| ((x, y), y == 2 && (1..=3).contains(&x))))
+      .collect();
+    let mut life = Automaton::new(cells, Neighborhood::Moore);
+    let rule = |&alive: &bool, around: &[Option<&bool>]| {
+      let n = around.iter().filter(|&&a| a == Some(&true)).count();
+      n == 3 || (alive && n == 2)
+    };
+
+    life.step_sync(rule);
+    let mut live : Vec<Point> = life.cells().iter().filter(|(_, &a)| a).map(|(&p, _)| p).collect();
+    live.sort();
+    assert_eq!(live, vec![(2, 1), (2, 2), (2, 3)]);
+
+    life.step_sync(rule);
+    assert!(life.cells()[&(1, 2)] && life.cells()[&(3, 2)]);
+  }
+
+  #[test]
+  fn test_step_cascade() {
+    struct Topple;
+    impl CascadeRule for Topple {
+      type Cell = usize;
+      fn charge(&self, cell: &mut usize) -> bool {
+        *cell += 1;
+        *cell == 4
+      }
+      fn settle(&self, cell: &mut usize) {
+        *cell = 0;
+      }
+    }
+
+    let cells : HashMap<Point, usize> = [((0, 0), 3), ((1, 0), 2), ((2, 0), 1), ((3, 0), 0)].into_iter().collect();
+    let mut a = Automaton::new(cells, Neighborhood::Custom(vec![(-1, 0), (1, 0)]));
+
+    let fired = a.step_cascade(&Topple);
+    assert_eq!(fired.len(), 2);
+    assert_eq!(a.cells()[&(0, 0)], 0);
+    assert_eq!(a.cells()[&(1, 0)], 0);
+    assert_eq!(a.cells()[&(2, 0)], 3);
+    assert_eq!(a.cells()[&(3, 0)], 1);
+  }
+
+  // day 20: each pixel looks up the 3x3 window around it, read in reading
+  // order as a binary number, in the enhancement algorithm
+  fn enhance(input: &str, steps: usize) -> usize {
+    let (algorithm, image) = input.trim().split_once("\n\n").expect("shit");
+    let algorithm : Vec<bool> = algorithm.chars().map(|ch| ch == '#').collect();
+    let cells : HashMap<Point, bool> = image
+      .lines()
+      .enumerate()
+      .flat_map(|(y, line)| line.chars().enumerate().map(move |(x, ch)| ((x as isize, y as isize), ch == '#')))
+      .collect();
+
+    let window = (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))).collect();
+    let mut image = Automaton::new(cells, Neighborhood::Custom(window)).with_background(false);
+
+    for _ in 0..steps {
+      image.step_sync(|_, around| {
+        algorithm[around.iter().fold(0, |index, &lit| index << 1 | (lit == Some(&true)) as usize)]
+      });
+    }
+
+    assert_eq!(image.background(), Some(&false));
+    image.cells().values().filter(|&&lit| lit).count()
+  }
+
+  #[test]
+  fn test_background() {
+    assert_eq!(enhance(include_str!("../inputs/day20.sample.txt"), 2), 35);
+    // this algorithm lights an all dark window, so the background blinks
+    assert_eq!(enhance(include_str!("../inputs/day20.sample2.txt"), 2), 5326);
+  }
+
+  #[test]
+  fn test_wrapping() {
+    // day 25: the east facing herd moves into empty cells, all at once
+    let row = |s: &str| s.chars().enumerate().map(|(x, ch)| ((x as isize, 0), ch)).collect::<HashMap<Point, char>>();
+    let render = |a: &Automaton<char>| (0..a.len() as isize).map(|x| a.cells()[&(x, 0)]).collect::<String>();
+    let rule = |&cell: &char, around: &[Option<&char>]| match (cell, around[0], around[1]) {
+      ('.', Some('>'), _) => '>',
+      ('>', _, Some('.')) => '.',
+      _ => cell
+    };
+    let neighborhood = Neighborhood::Custom(vec![(-1, 0), (1, 0)]);
+
+    let mut herd = Automaton::new(row(">..>"), neighborhood.clone()).with_wrapping();
+    herd.step_sync(rule);
+    assert_eq!(render(&herd), ".>.>");
+    herd.step_sync(rule);
+    assert_eq!(render(&herd), ">.>.");
+    assert_eq!(herd.neighbors((3, 0)).collect::<Vec<_>>(), vec![(2, 0), (0, 0)]);
+
+    let mut bounded = Automaton::new(row(">..>"), neighborhood);
+    bounded.step_sync(rule);
+    bounded.step_sync(rule);
+    assert_eq!(render(&bounded), "..>>");
+  }
+}
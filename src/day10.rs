@@ -1,7 +1,8 @@
 use std::collections::HashMap;
+use std::str::FromStr;
 
-const INPUT : &'static str = include_str!("../inputs/day10.txt");
-const SAMPLE : &'static str = include_str!("../inputs/day10.sample.txt");
+const INPUT : &str = include_str!("../inputs/day10.txt");
+const SAMPLE : &str = include_str!("../inputs/day10.sample.txt");
 
 #[derive(Clone, Copy)]
 struct Chunk {
@@ -20,64 +21,371 @@ impl Chunk {
   }
 }
 
-lazy_static! {
+// what to do with characters that are neither openers nor closers
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Unknown {
+  Ignore,
+  Error
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum SyntaxError {
+  // closing char that doesn't match the open chunk, or closes nothing
+  Corrupted(char),
+  // char not in the grammar
+  Unknown(char)
+}
+
+// a syntax error and where it happened.  columns are 0-based char offsets.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Diagnostic {
+  error: SyntaxError,
+  column: usize,
+  // innermost open chunk, which a closer was expected to match
+  expected: Option<(usize, char)>,
+  // every open chunk at the point of failure, outermost first
+  stack: Vec<(usize, char)>
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Checked {
+  diagnostics: Vec<Diagnostic>,
+  // closers needed to finish the line after any recovery
+  completion: String
+}
+
+// a single change to a line.  columns refer to the original line; inserts
+// go before the character at that column (or at the end).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Edit {
+  Insert(usize, char),
+  Delete(usize, char),
+  Substitute(usize, char, char)
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Repair {
+  line: String,
+  edits: Vec<Edit>,
+  score: usize
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Step {
+  Empty,
+  Keep,
+  Delete,
+  Insert,
+  Pair(usize)
+}
+
+// the set of delimiter pairs understood by the checker, with their scores
+#[derive(Clone)]
+struct Grammar {
   // map of opening char to chunk
-  static ref OPENING : HashMap<char, Chunk> = [
+  opening: HashMap<char, Chunk>,
+  // map of closing char to chunk
+  closing: HashMap<char, Chunk>,
+  unknown: Unknown
+}
+
+impl Grammar {
+  pub fn new(chunks: &[Chunk]) -> Result<Self, &'static str> {
+    let mut opening = HashMap::new();
+    let mut closing = HashMap::new();
+
+    for &chunk in chunks {
+      if chunk.open == chunk.close {
+        return Err("Chunk opens and closes with the same character");
+      }
+      if opening.contains_key(&chunk.open) || closing.contains_key(&chunk.open) ||
+        opening.contains_key(&chunk.close) || closing.contains_key(&chunk.close) {
+        return Err("Character used by more than one chunk");
+      }
+      opening.insert(chunk.open, chunk);
+      closing.insert(chunk.close, chunk);
+    }
+
+    Ok(Grammar { opening, closing, unknown: Unknown::Error })
+  }
+
+  pub fn with_unknown(mut self, unknown: Unknown) -> Self {
+    self.unknown = unknown;
+    self
+  }
+
+  // parse a single line.  If it is incomplete, returns Ok(completion).
+  // otherwise returns the first error
+  pub fn parse(&self, line: &str) -> Result<String, SyntaxError> {
+    let checked = self.check(line, false);
+
+    match checked.diagnostics.first() {
+      Some(d) => Err(d.error),
+      None => Ok(checked.completion)
+    }
+  }
+
+  // parse a line collecting positioned diagnostics.  with `all` set, carries
+  // on after each error: a closer matching a chunk further down the stack
+  // closes everything above it, any other bad character is skipped.
+  pub fn check(&self, line: &str, all: bool) -> Checked {
+    let mut stack : Vec<(usize, char)> = vec![];
+    let mut diagnostics = vec![];
+
+    for (column, ch) in line.chars().enumerate() {
+      let error = if self.opening.contains_key(&ch) {
+        stack.push((column, ch));
+        None
+      } else if self.closing.contains_key(&ch) {
+        match stack.last() {
+          Some(&(_, o)) if self.opening[&o].is_close(ch) => {
+            stack.pop();
+            None
+          },
+          _ => Some(SyntaxError::Corrupted(ch))
+        }
+      } else if self.unknown == Unknown::Error {
+        Some(SyntaxError::Unknown(ch))
+      } else {
+        None
+      };
+
+      if let Some(error) = error {
+        diagnostics.push(Diagnostic { error, column, expected: stack.last().copied(), stack: stack.clone() });
+
+        if !all {
+          break;
+        }
+        if let Some(index) = stack.iter().rposition(|&(_, o)| self.opening[&o].is_close(ch)) {
+          stack.truncate(index);
+        }
+      }
+    }
+
+    // map remaining opened chunks to their closing characters
+    let completion : String = stack
+      .iter()
+      .rev()
+      .map(|(_, ch)| self.opening[ch].close)
+      .collect();
+
+    Checked { diagnostics, completion }
+  }
+
+  // cheapest set of insertions, deletions and substitutions leaving the line
+  // balanced.  interval dp over the line: each character either pairs with a
+  // later one (substituting one or both if they don't already match), gets a
+  // partner inserted next to it, or is deleted.  ignored characters are kept,
+  // unknown ones may be substituted into either half of a pair.
+  pub fn repair(&self, line: &str) -> Repair {
+    let chars : Vec<char> = line.chars().collect();
+    let n = chars.len();
+    // cost[i][j] and the step taken for the segment chars[i..j]
+    let mut cost = vec![vec![0usize; n + 1]; n + 1];
+    let mut step = vec![vec![Step::Empty; n + 1]; n + 1];
+
+    for len in 1..=n {
+      for i in 0..=(n - len) {
+        let j = i + len;
+        let ch = chars[i];
+
+        let (mut best, mut best_step) = if self.is_delimiter(ch) {
+          (1 + cost[i + 1][j], Step::Insert)
+        } else if self.unknown == Unknown::Ignore {
+          (cost[i + 1][j], Step::Keep)
+        } else {
+          // substituting and inserting a partner would cost 2, deleting is cheaper
+          (1 + cost[i + 1][j], Step::Delete)
+        };
+
+        for k in (i + 1)..j {
+          if let Some(pair) = self.pair_cost(ch, chars[k]) {
+            let total = pair + cost[i + 1][k] + cost[k + 1][j];
+            if total < best {
+              best = total;
+              best_step = Step::Pair(k);
+            }
+          }
+        }
+
+        cost[i][j] = best;
+        step[i][j] = best_step;
+      }
+    }
+
+    let mut fixed = String::new();
+    let mut edits = vec![];
+    self.rebuild(&chars, &step, 0, n, &mut fixed, &mut edits);
+
+    let score = edits.iter().map(|edit| self.edit_score(edit)).sum();
+    Repair { line: fixed, edits, score }
+  }
+
+  fn rebuild(&self, chars: &[char], step: &[Vec<Step>], i: usize, j: usize, out: &mut String, edits: &mut Vec<Edit>) {
+    if i >= j {
+      return;
+    }
+    let ch = chars[i];
+
+    match step[i][j] {
+      Step::Empty => {},
+      Step::Keep => {
+        out.push(ch);
+        self.rebuild(chars, step, i + 1, j, out, edits);
+      },
+      Step::Delete => {
+        edits.push(Edit::Delete(i, ch));
+        self.rebuild(chars, step, i + 1, j, out, edits);
+      },
+      Step::Insert => {
+        if let Some(chunk) = self.opening.get(&ch) {
+          // close the chunk once the rest of the segment is balanced
+          out.push(ch);
+          self.rebuild(chars, step, i + 1, j, out, edits);
+          out.push(chunk.close);
+          edits.push(Edit::Insert(j, chunk.close));
+        } else {
+          // open a chunk right before the stray closer
+          let open = self.closing[&ch].open;
+          edits.push(Edit::Insert(i, open));
+          out.push(open);
+          out.push(ch);
+          self.rebuild(chars, step, i + 1, j, out, edits);
+        }
+      },
+      Step::Pair(k) => {
+        let other = chars[k];
+        let chunk = self.opening.get(&ch)
+          .or_else(|| self.closing.get(&other))
+          .or_else(|| self.closing.get(&ch))
+          .or_else(|| self.opening.get(&other))
+          // both unknown, any chunk will do
+          .unwrap_or_else(|| self.opening.iter().min_by_key(|(&open, _)| open).unwrap().1);
+        let (open, close) = (chunk.open, chunk.close);
+
+        if open != ch {
+          edits.push(Edit::Substitute(i, ch, open));
+        }
+        out.push(open);
+        self.rebuild(chars, step, i + 1, k, out, edits);
+        if close != other {
+          edits.push(Edit::Substitute(k, other, close));
+        }
+        out.push(close);
+        self.rebuild(chars, step, k + 1, j, out, edits);
+      }
+    }
+  }
+
+  fn is_delimiter(&self, ch: char) -> bool {
+    self.opening.contains_key(&ch) || self.closing.contains_key(&ch)
+  }
+
+  // substitutions needed for `a` to open a chunk that `b` closes.  ignored
+  // characters can't take part, unknown ones are always substituted.
+  fn pair_cost(&self, a: char, b: char) -> Option<usize> {
+    let usable = |ch: char| self.is_delimiter(ch) || self.unknown == Unknown::Error;
+    if !usable(a) || !usable(b) {
+      return None;
+    }
+
+    match self.opening.get(&a) {
+      Some(chunk) if chunk.is_close(b) => Some(0),
+      Some(_) => Some(1),
+      None if self.closing.contains_key(&b) => Some(1),
+      None => Some(2)
+    }
+  }
+
+  // error score of the chunk each edit touches: the inserted character, or
+  // the original for deletions and substitutions
+  fn edit_score(&self, edit: &Edit) -> usize {
+    let ch = match *edit {
+      Edit::Insert(_, ch) | Edit::Delete(_, ch) | Edit::Substitute(_, ch, _) => ch
+    };
+
+    self.opening
+      .get(&ch)
+      .or_else(|| self.closing.get(&ch))
+      .map_or(0, |chunk| chunk.error_score)
+  }
+
+  // score of a single error, unknown characters score nothing
+  pub fn error_score(&self, err: &SyntaxError) -> usize {
+    match err {
+      SyntaxError::Corrupted(ch) => self.closing[ch].error_score,
+      SyntaxError::Unknown(_) => 0
+    }
+  }
+
+  // given a single completion, calculate its score
+  pub fn completion_score(&self, completion: &str) -> usize {
+    completion
+      .chars()
+      .map(|ch| self.closing[&ch].completion_score)
+      .fold(0, |total, char_score| total * 5 + char_score)
+  }
+}
+
+// one chunk per line: opening char, closing char, error score, completion score
+impl FromStr for Grammar {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let chunks = s
+      .lines()
+      .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+      .filter(|parts| !parts.is_empty())
+      .map(|parts| {
+        let ch = |s: &str| {
+          let mut chars = s.chars();
+          match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c),
+            _ => Err("Delimiter must be a single character")
+          }
+        };
+        let num = |s: &str| s.parse::<usize>().map_err(|_| "Invalid score");
+
+        match parts.as_slice() {
+          &[open, close, error, completion] => Ok(Chunk::new(ch(open)?, ch(close)?, num(error)?, num(completion)?)),
+          _ => Err("Expected: open close error_score completion_score")
+        }
+      })
+      .collect::<Result<Vec<Chunk>, _>>()?;
+
+    Grammar::new(&chunks)
+  }
+}
+
+lazy_static! {
+  static ref STANDARD : Grammar = Grammar::new(&[
     Chunk::new('(', ')', 3, 1),    Chunk::new('[', ']', 57, 2),
     Chunk::new('{', '}', 1197, 3), Chunk::new('<', '>', 25137, 4),
-  ].into_iter().map(|chunk| (chunk.open, chunk)).collect();
-  // map of closing char to chunk
-  static ref CLOSING : HashMap<char, Chunk> = OPENING.values().map(|&chunk| (chunk.close, chunk)).collect();
-}
-
-// parse a single line.  If it is incomplete, returns Ok(completion).
-// otherwise returns Err(mismatched_closing_char)
-fn parse(line: &str) -> Result<String, char> {
-  let incomplete = line
-    .chars()
-    .try_fold(vec![], |mut stack, ch| match ch {
-        '[' | '{' | '<' | '(' => {
-          stack.push(ch);
-          Ok(stack)
-        },
-        ']' | '}' | '>' | ')' if !stack.is_empty() => {
-          let o = stack.pop().unwrap();
-          if OPENING.get(&o).unwrap().is_close(ch) { Ok(stack) } else { Err(ch) }
-        },
-        _ if stack.is_empty() => panic!("Closing character without opening"),
-        _ => panic!("Invalid character: {}", ch)
-    })?;
-
-  // map remaining opened chunks to their closing characters
-  let completion : String = incomplete
-    .iter()
-    .rev()
-    .map(|ch| OPENING.get(ch).unwrap().close)
-    .collect();
-
-  Ok(completion)
-}
-
-fn parse_lines<'a>(input: &'a &str) -> impl Iterator<Item=Result<String, char>> + 'a {
+  ]).unwrap();
+}
+
+// parse a single line with the standard grammar
+fn parse(line: &str) -> Result<String, SyntaxError> {
+  STANDARD.parse(line)
+}
+
+fn parse_lines<'a>(input: &'a str) -> impl Iterator<Item=Result<String, SyntaxError>> + 'a {
   input.lines().map(parse)
 }
 
 // given list of errors, return the total score
-fn error_score(errs: &[char]) -> usize {
-  errs.iter().map(|ch| CLOSING.get(ch).unwrap().error_score).sum()
+fn error_score(errs: &[SyntaxError]) -> usize {
+  errs.iter().map(|err| STANDARD.error_score(err)).sum()
 }
 
 // given a single completion, calculate its score
 fn completion_score(completion: &str) -> usize {
-  completion
-    .chars()
-    .map(|ch| CLOSING.get(&ch).unwrap().completion_score)
-    .fold(0, |total, char_score| total * 5 + char_score)
+  STANDARD.completion_score(completion)
 }
 
 // given a list of completions, calculate the middle score
 fn completions_middle_score(completions: &[String]) -> usize {
-  let mut scores : Vec<usize> = completions.iter().map(|c| completion_score(&c)).collect();
+  let mut scores : Vec<usize> = completions.iter().map(|c| completion_score(c)).collect();
   scores.sort();
 
   scores[scores.len() / 2]
@@ -89,22 +397,23 @@ mod tests {
 
   #[test]
   fn part1_example() {
-    let errs : Vec<char> = parse_lines(&SAMPLE).filter_map(|r| r.err()).collect();
+    let errs : Vec<SyntaxError> = parse_lines(SAMPLE).filter_map(|r| r.err()).collect();
+    let chars : Vec<char> = errs.iter().map(|e| match e { SyntaxError::Corrupted(ch) | SyntaxError::Unknown(ch) => *ch }).collect();
 
-    assert_eq!(&errs, &['}', ')', ']', ')', '>']);
+    assert_eq!(&chars, &['}', ')', ']', ')', '>']);
     assert_eq!(error_score(&errs), 26397);
   }
 
   #[test]
   fn part1_solution() {
-    let errs : Vec<char> = parse_lines(&INPUT).filter_map(|r| r.err()).collect();
+    let errs : Vec<SyntaxError> = parse_lines(INPUT).filter_map(|r| r.err()).collect();
 
     assert_eq!(error_score(&errs), 323691);
   }
 
   #[test]
   fn part2_example() {
-    let incomplete : Vec<String> = parse_lines(&SAMPLE).filter_map(|r| r.ok()).collect();
+    let incomplete : Vec<String> = parse_lines(SAMPLE).filter_map(|r| r.ok()).collect();
 
     assert_eq!(&incomplete, &["}}]])})]", ")}>]})", "}}>}>))))", "]]}}]}]}>", "])}>"]);
     assert_eq!(completions_middle_score(&incomplete), 288957);
@@ -112,8 +421,120 @@ mod tests {
 
   #[test]
   fn part2_solution() {
-    let incomplete : Vec<String> = parse_lines(&INPUT).filter_map(|r| r.ok()).collect();
+    let incomplete : Vec<String> = parse_lines(INPUT).filter_map(|r| r.ok()).collect();
 
     assert_eq!(completions_middle_score(&incomplete), 2858785164);
   }
+
+  #[test]
+  fn test_grammar() {
+    assert_eq!(parse("a"), Err(SyntaxError::Unknown('a')));
+    assert_eq!(parse(")"), Err(SyntaxError::Corrupted(')')));
+    assert_eq!(STANDARD.clone().with_unknown(Unknown::Ignore).parse("(a[b"), Ok("])".to_owned()));
+
+    let html : Grammar = "< > 10 1\n/ \\ 20 2".parse().expect("shit");
+    let html = html.with_unknown(Unknown::Ignore);
+    assert_eq!(html.parse("<p/x\\>"), Ok("".to_owned()));
+    assert_eq!(html.parse("<p/x>"), Err(SyntaxError::Corrupted('>')));
+    assert_eq!(html.error_score(&SyntaxError::Corrupted('>')), 10);
+    assert_eq!(html.completion_score("\\>"), 11);
+
+    assert!("\" \" 1 1".parse::<Grammar>().is_err());
+    assert!("( ) 1 1\n[ ( 2 2".parse::<Grammar>().is_err());
+    assert!("( ) x 1".parse::<Grammar>().is_err());
+  }
+
+  #[test]
+  fn test_diagnostics() {
+    let checked = STANDARD.check("{([(<{}[<>[]}>{[]{[(<()>", false);
+
+    assert_eq!(checked.diagnostics, vec![Diagnostic {
+      error: SyntaxError::Corrupted('}'),
+      column: 12,
+      expected: Some((7, '[')),
+      stack: vec![(0, '{'), (1, '('), (2, '['), (3, '('), (4, '<'), (7, '[')]
+    }]);
+
+    let checked = STANDARD.check("(]x<)>)", true);
+    assert_eq!(
+      checked.diagnostics.iter().map(|d| (d.error, d.column, d.expected)).collect::<Vec<_>>(),
+      vec![
+        (SyntaxError::Corrupted(']'), 1, Some((0, '('))),
+        (SyntaxError::Unknown('x'), 2, Some((0, '('))),
+        (SyntaxError::Corrupted(')'), 4, Some((3, '<'))),
+        (SyntaxError::Corrupted('>'), 5, None),
+        (SyntaxError::Corrupted(')'), 6, None)
+      ]
+    );
+    assert_eq!(checked.completion, "");
+
+    let checked = STANDARD.check("[({(<(())[]>[[{[]{<()<>>", true);
+    assert!(checked.diagnostics.is_empty());
+    assert_eq!(checked.completion, "}}]])})]");
+  }
+
+  #[test]
+  fn test_repair() {
+    assert_eq!(STANDARD.repair("(]"), Repair { line: "()".to_owned(), edits: vec![Edit::Substitute(1, ']', ')')], score: 57 });
+    assert_eq!(STANDARD.repair("())").edits.len(), 1);
+    assert_eq!(STANDARD.repair("x").edits, vec![Edit::Delete(0, 'x')]);
+    assert_eq!(STANDARD.repair("x)").edits, vec![Edit::Substitute(0, 'x', '(')]);
+    assert_eq!(STANDARD.repair("(x").edits, vec![Edit::Substitute(1, 'x', ')')]);
+    assert_eq!(STANDARD.repair("xx").edits.len(), 2);
+    assert_eq!(STANDARD.clone().with_unknown(Unknown::Ignore).repair("(x").line, "(x)");
+
+    assert_eq!(
+      STANDARD.repair("[()"),
+      Repair { line: "[()]".to_owned(), edits: vec![Edit::Insert(3, ']')], score: 57 }
+    );
+    // turning openers into closers can beat appending the completion
+    let repair = STANDARD.repair("<{([");
+    assert_eq!(repair.edits.len(), 2);
+    assert_eq!(parse(&repair.line), Ok("".to_owned()));
+
+    for line in SAMPLE.lines() {
+      let repair = STANDARD.repair(line);
+      assert_eq!(parse(&repair.line), Ok("".to_owned()));
+      if let Ok(completion) = parse(line) {
+        assert!(repair.edits.len() <= completion.len());
+      }
+    }
+  }
+
+  #[test]
+  fn test_repair_is_minimal() {
+    fn distance(a: &[char], b: &[char]) -> usize {
+      let mut prev : Vec<usize> = (0..=b.len()).collect();
+      for (i, ca) in a.iter().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+          cur.push((prev[j] + usize::from(ca != cb)).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+      }
+      prev[b.len()]
+    }
+
+    fn strings(len: usize, alphabet: &str) -> Vec<String> {
+      (0..len).fold(vec![String::new()], |acc, _| {
+        acc.iter().flat_map(|s| alphabet.chars().map(move |c| format!("{}{}", s, c))).collect()
+      })
+    }
+
+    let balanced : Vec<Vec<char>> = (0..=8)
+      .flat_map(|len| strings(len, "([)]"))
+      .filter(|s| parse(s) == Ok("".to_owned()))
+      .map(|s| s.chars().collect())
+      .collect();
+
+    // x is unknown to the grammar, so it has to be deleted or substituted
+    for line in (0..=4).flat_map(|len| strings(len, "([)]x")) {
+      let chars : Vec<char> = line.chars().collect();
+      let best = balanced.iter().map(|b| distance(&chars, b)).min().unwrap();
+      let repair = STANDARD.repair(&line);
+
+      assert_eq!(repair.edits.len(), best, "{}", line);
+      assert_eq!(parse(&repair.line), Ok("".to_owned()));
+    }
+  }
 }
@@ -1,14 +1,44 @@
 use std::str::FromStr;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
+use crate::automaton::{Automaton, CascadeRule, Neighborhood, Point};
 
-const INPUT : &'static str = include_str!("../inputs/day11.txt");
-const SAMPLE : &'static str = include_str!("../inputs/day11.sample.txt");
+const INPUT : &str = include_str!("../inputs/day11.txt");
+const SAMPLE : &str = include_str!("../inputs/day11.sample.txt");
 
-type Point = (isize, isize);
+// octopuses gain one energy per step and from each flashing neighbour,
+// flash once above 9 and drop back to 0
+struct Octopus;
+
+impl CascadeRule for Octopus {
+  type Cell = usize;
+
+  fn charge(&self, energy: &mut usize) -> bool {
+    *energy += 1;
+    *energy == 10
+  }
+
+  fn settle(&self, energy: &mut usize) {
+    *energy = 0;
+  }
+}
+
+// the grid state after `start` steps comes back every `period` steps
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Cycle {
+  start: usize,
+  period: usize
+}
 
 #[derive(Clone)]
 struct Grid {
-  cells: HashMap<Point, usize>
+  automaton: Automaton<usize>,
+  // cells in reading order, for snapshots
+  order: Vec<Point>,
+  // flashes in each step taken so far
+  history: Vec<usize>,
+  // step count at which each state was first seen
+  seen: HashMap<Vec<usize>, usize>,
+  cycle: Option<Cycle>
 }
 
 impl FromStr for Grid {
@@ -30,77 +60,98 @@ impl FromStr for Grid {
           })
       })
       .collect::<Result<HashMap<Point, usize>, Self::Err>>()
-      .map(|cells| Grid { cells })
+      .map(|cells| Grid::new(Automaton::new(cells, Neighborhood::Moore)))
   }
 }
 
 impl Grid {
-  fn step(&mut self) -> usize {
-    // set of points that flashed this iteration
-    let mut seen : HashSet<Point> = HashSet::new();
-
-    // first increment all cells
-    self
-      .cells
-      .iter_mut()
-      .for_each(|(_, value)| *value += 1 );
-
-    loop {
-      // find cells that should flash
-      let flashes : Vec<Point> = self
-        .cells
-        .iter()
-        .filter(|(point, &value)| !seen.contains(point) && value > 9)
-        .map(|(point, _)| point)
-        .cloned()
-        .collect::<Vec<Point>>();
-
-      // mark as seen so we don't double flash
-      for f in &flashes {
-        seen.insert(*f);
-      }
+  fn new(automaton: Automaton<usize>) -> Self {
+    let mut order : Vec<Point> = automaton.cells().keys().copied().collect();
+    order.sort_by_key(|&(x, y)| (y, x));
 
-      // find neighbors of flashes
-      let to_increment = flashes
-        .iter()
-        .flat_map(|&point| self.neighbors(point))
-        .collect::<Vec<Point>>();
+    let mut grid = Grid { automaton, order, history: vec![], seen: HashMap::new(), cycle: None };
+    grid.seen.insert(grid.snapshot(), 0);
+    grid
+  }
 
-      // no neighbors to increment means we're done
-      if to_increment.is_empty() {
-        break;
-      }
+  fn snapshot(&self) -> Vec<usize> {
+    self.order.iter().map(|p| self.automaton.cells()[p]).collect()
+  }
 
-      // increment neighbors
-      for point in to_increment {
-        self.cells
-          .get_mut(&point)
-          .map(|v| *v += 1);
+  // total number of flashes this step
+  fn step(&mut self) -> usize {
+    let flashes = self.automaton.step_cascade(&Octopus).len();
+    self.history.push(flashes);
+
+    if self.cycle.is_none() {
+      let steps = self.history.len();
+      if let Some(&start) = self.seen.get(&self.snapshot()) {
+        self.cycle = Some(Cycle { start, period: steps - start });
+        // the cycle is all we need from here on
+        self.seen.clear();
+      } else {
+        self.seen.insert(self.snapshot(), steps);
       }
     }
 
-    // set all cells which flashed to zero
-    for pos in &seen {
-      self.cells.get_mut(&pos).map(|v| *v = 0);
-    }
-
-    // total number of flashes this iteration
-    seen.len()
+    flashes
   }
 
   fn run(&mut self, steps: usize) -> usize {
     (0..steps).map(|_| self.step()).sum()
   }
 
+  // next step on which every octopus flashes, or None if the grid goes a
+  // whole cycle without syncing
   fn run_until_sync(&mut self) -> Option<usize> {
-    (1..usize::MAX).find(|_| self.step() == self.cells.len())
+    let mut cycling = 0;
+
+    loop {
+      if self.step() == self.automaton.len() {
+        return Some(self.history.len());
+      }
+      if let Some(cycle) = self.cycle {
+        // every state in the cycle has been seen once `period` steps in
+        cycling += 1;
+        if cycling >= cycle.period {
+          return None;
+        }
+      }
+    }
+  }
+
+  // step until the grid state repeats or `limit` steps have been taken
+  fn find_cycle(&mut self, limit: usize) -> Option<Cycle> {
+    while self.cycle.is_none() && self.history.len() < limit {
+      self.step();
+    }
+    self.cycle
   }
 
-  fn neighbors<'a>(&'a self, pos: Point) -> impl Iterator<Item=Point> + 'a {
-    [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)]
-      .into_iter()
-      .map(move |(dx, dy)| (pos.0 + dx, pos.1 + dy))
-      .filter(|p| self.cells.contains_key(&p))
+  // flashes over the first `steps` steps from the starting grid, extrapolated
+  // from the cycle once one is found.  None if no cycle turns up within `limit` steps.
+  fn total_flashes(&mut self, steps: u64, limit: usize) -> Option<u128> {
+    let sum = |range: &[usize]| range.iter().map(|&n| n as u128).sum::<u128>();
+
+    if steps as u128 <= self.history.len() as u128 {
+      return Some(sum(&self.history[..steps as usize]));
+    }
+
+    // stepping up to `steps` directly is cheaper than finding the cycle
+    while self.cycle.is_none() && (self.history.len() as u64) < steps && self.history.len() < limit {
+      self.step();
+    }
+    if steps as u128 <= self.history.len() as u128 {
+      return Some(sum(&self.history[..steps as usize]));
+    }
+
+    let Cycle { start, period } = self.cycle?;
+    let (start, period) = (start as u64, period as u64);
+    let repeat = &self.history[start as usize..(start + period) as usize];
+    let cycles = (steps - start) / period;
+    let remainder = ((steps - start) % period) as usize;
+
+    Some(sum(&self.history[..start as usize]) + cycles as u128 * sum(repeat) + sum(&repeat[..remainder]))
   }
 }
 
@@ -131,4 +182,47 @@ mod tests {
     let mut g : Grid = INPUT.parse().expect("shit");
     assert_eq!(g.run_until_sync(), Some(251));
   }
+
+  #[test]
+  fn test_history() {
+    let mut g : Grid = SAMPLE.parse().expect("shit");
+    g.run(10);
+    assert_eq!(g.history, vec![0, 35, 45, 16, 8, 1, 7, 24, 39, 29]);
+  }
+
+  #[test]
+  fn test_cycle() {
+    let mut g : Grid = SAMPLE.parse().expect("shit");
+    let cycle = g.find_cycle(1000).expect("shit");
+
+    // once synced every octopus flashes together every 10 steps
+    assert_eq!(cycle, Cycle { start: 195, period: 10 });
+    assert_eq!(g.history[194], 100);
+
+    let mut fresh : Grid = SAMPLE.parse().expect("shit");
+    assert_eq!(fresh.total_flashes(100, 1000), Some(1656));
+    assert_eq!(fresh.total_flashes(205, 1000), Some(g.history[..205].iter().sum::<usize>() as u128));
+
+    let huge = 1_000_000_000_000u64;
+    let before : u128 = g.history[..195].iter().map(|&n| n as u128).sum();
+    assert_eq!(g.total_flashes(huge, 1000), Some(before + (huge as u128 - 195) / 10 * 100));
+
+    // a known cycle still has to be stepped through to find the next sync
+    let mut cycled : Grid = SAMPLE.parse().expect("shit");
+    cycled.find_cycle(1000);
+    assert_eq!(cycled.run_until_sync(), Some(215));
+    assert_eq!(cycled.run_until_sync(), Some(225));
+
+    let mut short : Grid = SAMPLE.parse().expect("shit");
+    assert_eq!(short.find_cycle(50), None);
+    assert_eq!(short.total_flashes(huge, 50), None);
+  }
+
+  #[test]
+  fn test_never_syncs() {
+    // this row settles into a cycle where the octopuses never all flash at once
+    let mut g : Grid = "002".parse().expect("shit");
+    assert_eq!(g.run_until_sync(), None);
+    assert!(g.cycle.is_some());
+  }
 }
@@ -1,11 +1,21 @@
 use std::collections::{HashMap,HashSet};
+use std::hash::Hash;
+use std::fmt::Write;
 use std::str::FromStr;
 
-const INPUT : &'static str = include_str!("../inputs/day12.txt");
-const SAMPLE : &'static str = include_str!("../inputs/day12.sample.txt");
+const INPUT : &str = include_str!("../inputs/day12.txt");
+const SAMPLE : &str = include_str!("../inputs/day12.sample.txt");
+
+const INFINITE : &str = "Infinitely many paths";
 
 struct Caves {
-  caves: HashMap<String, HashSet<String>>
+  caves: HashMap<String, HashSet<String>>,
+  // caves interned to ids, in sorted name order
+  names: Vec<String>,
+  ids: HashMap<String, usize>,
+  adjacency: Vec<Vec<usize>>,
+  // bit marking each small cave in a visited mask, None for big caves
+  small: Vec<Option<u64>>
 }
 
 impl FromStr for Caves {
@@ -37,69 +47,331 @@ impl FromStr for Caves {
           hash
         })
       })
-      .map(|hash| Caves { caves: hash })
+      .and_then(Caves::new)
   }
 }
 
 impl Caves {
-  pub fn total_paths(&self) -> usize {
-    self.paths_from("start", "end", HashSet::new())
-  }
+  fn new(caves: HashMap<String, HashSet<String>>) -> Result<Self, &'static str> {
+    let mut names : Vec<String> = caves.keys().cloned().collect();
+    names.sort();
 
-  pub fn paths_from(&self, from: &str, to: &str, mut forbidden: HashSet<String>) -> usize {
-    forbidden.insert(from.to_owned());
+    let ids : HashMap<String, usize> = names
+      .iter()
+      .enumerate()
+      .map(|(id, name)| (name.clone(), id))
+      .collect();
 
-    if from == to {
-      return 1;
-    }
+    let adjacency = names
+      .iter()
+      .map(|name| {
+        let mut next : Vec<usize> = caves[name].iter().map(|n| ids[n]).collect();
+        next.sort();
+        next
+      })
+      .collect();
 
-    self
-      .neighbors(from)
+    let mut bits = 0;
+    let small = names
       .iter()
-      .filter(|&neighbor| !Caves::is_small(neighbor) || !forbidden.contains(neighbor))
-      .map(|neighbor| self.paths_from(neighbor, to, forbidden.clone()))
-      .sum()
+      .map(|name| {
+        if !Caves::is_small(name) {
+          return Ok(None);
+        }
+        if bits == u64::BITS {
+          return Err("Too many small caves");
+        }
+        bits += 1;
+        Ok(Some(1 << (bits - 1)))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Caves { caves, names, ids, adjacency, small })
   }
 
+  pub fn total_paths(&self) -> Result<usize, &'static str> {
+    self.count_paths("start", "end", &SmallRevisits::new(0))
+  }
 
-  pub fn total_paths_2(&self) -> usize {
-    self.paths_from_2("start", "end", HashMap::new())
+  pub fn total_paths_2(&self) -> Result<usize, &'static str> {
+    self.count_paths("start", "end", &SmallRevisits::new(1))
   }
 
   pub fn neighbors(&self, node: &str) -> &HashSet<String> {
     self.caves.get(node).unwrap()
   }
 
-  pub fn paths_from_2(&self, from: &str, to: &str, mut forbidden: HashMap<String, usize>) -> usize {
-    let count = forbidden
-      .entry(from.to_owned())
-      .or_insert(0);
+  pub fn name(&self, cave: usize) -> &str {
+    &self.names[cave]
+  }
+
+  // bit marking a small cave in a visited mask, None for big caves
+  pub fn small_bit(&self, cave: usize) -> Option<u64> {
+    self.small[cave]
+  }
+
+  // paths from `from` to `to` allowed by `policy`.  fails if the policy
+  // lets a path loop forever (say between two big caves) and still reach `to`.
+  pub fn count_paths<P: VisitPolicy>(&self, from: &str, to: &str, policy: &P) -> Result<usize, &'static str> {
+    let (Some(&from), Some(&to)) = (self.ids.get(from), self.ids.get(to)) else { return Ok(0) };
+    let state = policy.initial(self, from);
+    let mut search = Search { memo: HashMap::new(), open: HashSet::new(), looped: HashSet::new() };
 
-    *count += 1;
+    self.count_from(from, to, state, policy, &mut search)
+  }
 
-    if from == to {
-      return 1;
+  // memoized on (cave, policy state).  reaching a state that is still open
+  // means the path can go round in a loop; that is only fatal once the loop
+  // turns out to lead to `to`.
+  fn count_from<P: VisitPolicy>(
+    &self, at: usize, to: usize, state: P::State, policy: &P, search: &mut Search<P::State>
+  ) -> Result<usize, &'static str> {
+    if at == to {
+      return Ok(1);
+    }
+    let key = (at, state);
+    if let Some(&n) = search.memo.get(&key) {
+      return Ok(n);
+    }
+    if search.open.contains(&key) {
+      search.looped.insert(key);
+      return Ok(0);
     }
 
-    self
-      .neighbors(from)
-      .iter()
-      .filter(|&neighbor|{
-        !Caves::is_small(neighbor) || // big caves A ok
-          !forbidden.contains_key(neighbor) || // never before seen cave sounds great
-          (forbidden
-           .iter()
-           .filter(|(k, _)| Caves::is_small(k)) // all small caves
-           .all(|(_, &v)| v == 1) // previously visited only once
-           && neighbor != "start" // cant go back to start u dipshit
-          )
-      })
-      .map(|neighbor| self.paths_from_2(neighbor, to, forbidden.clone()))
-      .sum()
+    search.open.insert(key.clone());
+    let mut total = 0;
+    for &next in &self.adjacency[at] {
+      if let Some(s) = policy.enter(self, &key.1, next) {
+        total += self.count_from(next, to, s, policy, search)?;
+      }
+    }
+    search.open.remove(&key);
+
+    if total > 0 && search.looped.contains(&key) {
+      return Err(INFINITE);
+    }
+    search.memo.insert(key, total);
+    Ok(total)
+  }
+
+  // lazily walk every path from `from` to `to` allowed by `policy`, in name
+  // order.  combine with `take`/`filter` to limit work.  fails like `count_paths`.
+  pub fn paths<'a, P: VisitPolicy>(&'a self, from: &str, to: &str, policy: &'a P) -> Result<Paths<'a, P>, &'static str> {
+    self.count_paths(from, to, policy)?;
+
+    let stack = match (self.ids.get(from), self.ids.get(to)) {
+      (Some(&from), Some(_)) => vec![Frame { at: from, next: 0, state: policy.initial(self, from) }],
+      _ => vec![]
+    };
+
+    Ok(Paths { caves: self, policy, to: self.ids.get(to).copied().unwrap_or(0), stack })
+  }
+
+  // paths formatted as in the puzzle text (`start,A,b,end`), filtered,
+  // optionally capped at `limit` paths, and sorted
+  pub fn listed_paths<P, F>(&self, policy: &P, limit: Option<usize>, filter: F) -> Result<Vec<String>, &'static str>
+  where
+    P: VisitPolicy,
+    F: Fn(&[&str]) -> bool
+  {
+    let mut listed : Vec<String> = self
+      .paths("start", "end", policy)?
+      .filter(|path| filter(path))
+      .take(limit.unwrap_or(usize::MAX))
+      .map(|path| path.join(","))
+      .collect();
+
+    listed.sort();
+    Ok(listed)
   }
 
   fn is_small(cave: &str) -> bool {
-    cave.to_lowercase() == cave
+    !cave.chars().any(|ch| ch.is_uppercase())
+  }
+
+  // graphviz rendering of the cave map: big caves are boxes, small caves
+  // ellipses.  caves and tunnels along `highlight` are drawn in red.
+  pub fn to_dot(&self, highlight: Option<&[&str]>) -> String {
+    let path = highlight.unwrap_or(&[]);
+    let on_path : HashSet<&str> = path.iter().copied().collect();
+    let walked : HashSet<(&str, &str)> = path
+      .windows(2)
+      .map(|w| if w[0] < w[1] { (w[0], w[1]) } else { (w[1], w[0]) })
+      .collect();
+
+    let mut names : Vec<&String> = self.caves.keys().collect();
+    names.sort();
+
+    let mut out = String::from("graph caves {\n");
+
+    for name in &names {
+      let shape = if Caves::is_small(name) { "ellipse" } else { "box" };
+      let style = if on_path.contains(name.as_str()) { ", color=red, penwidth=2" } else { "" };
+      writeln!(out, "  {:?} [shape={}{}];", name, shape, style).unwrap();
+    }
+
+    for a in &names {
+      let mut next : Vec<&String> = self.caves[*a].iter().filter(|b| a < b).collect();
+      next.sort();
+
+      for b in next {
+        let style = if walked.contains(&(a.as_str(), b.as_str())) { " [color=red, penwidth=2]" } else { "" };
+        writeln!(out, "  {:?} -- {:?}{};", a, b, style).unwrap();
+      }
+    }
+
+    out.push_str("}\n");
+    out
+  }
+}
+
+// decides which caves a path may enter.  `State` is whatever the policy
+// tracks along a path; it is part of the memo key when counting, so keep it small.
+trait VisitPolicy {
+  type State: Clone + Eq + Hash;
+
+  // state for a path that has just entered `start`
+  fn initial(&self, caves: &Caves, start: usize) -> Self::State;
+
+  // state after entering `cave`, or None if the path may not go there
+  fn enter(&self, caves: &Caves, state: &Self::State, cave: usize) -> Option<Self::State>;
+}
+
+// small caves at most once each, with up to `revisits` extra visits to small
+// caves other than the starting one.  part 1 allows 0, part 2 allows 1.
+struct SmallRevisits {
+  revisits: usize
+}
+
+impl SmallRevisits {
+  pub fn new(revisits: usize) -> Self {
+    SmallRevisits { revisits }
+  }
+}
+
+impl VisitPolicy for SmallRevisits {
+  // (start cave, small caves visited, revisits left)
+  type State = (usize, u64, usize);
+
+  fn initial(&self, caves: &Caves, start: usize) -> Self::State {
+    (start, caves.small_bit(start).unwrap_or(0), self.revisits)
+  }
+
+  fn enter(&self, caves: &Caves, &(start, visited, left): &Self::State, cave: usize) -> Option<Self::State> {
+    match caves.small_bit(cave) {
+      None => Some((start, visited, left)),
+      Some(bit) if visited & bit == 0 => Some((start, visited | bit, left)),
+      Some(_) if left > 0 && cave != start => Some((start, visited, left - 1)),
+      _ => None
+    }
+  }
+}
+
+// per cave visit limits: small caves default to once, big caves to unlimited,
+// and any cave can be given its own quota (0 forbids it entirely)
+struct Quotas {
+  quotas: HashMap<String, usize>
+}
+
+impl Quotas {
+  pub fn new() -> Self {
+    Quotas { quotas: HashMap::new() }
+  }
+
+  pub fn limit(mut self, cave: &str, visits: usize) -> Self {
+    self.quotas.insert(cave.to_owned(), visits);
+    self
+  }
+
+  pub fn forbid(self, cave: &str) -> Self {
+    self.limit(cave, 0)
+  }
+
+  fn quota(&self, caves: &Caves, cave: usize) -> Option<usize> {
+    match self.quotas.get(caves.name(cave)) {
+      Some(&q) => Some(q),
+      None if caves.small_bit(cave).is_some() => Some(1),
+      None => None
+    }
+  }
+}
+
+impl VisitPolicy for Quotas {
+  // visits so far to each cave with a quota, indexed by cave id
+  type State = Vec<usize>;
+
+  fn initial(&self, caves: &Caves, start: usize) -> Self::State {
+    let mut visits = vec![0; caves.names.len()];
+    if self.quota(caves, start).is_some() {
+      visits[start] = 1;
+    }
+    visits
+  }
+
+  fn enter(&self, caves: &Caves, visits: &Self::State, cave: usize) -> Option<Self::State> {
+    match self.quota(caves, cave) {
+      None => Some(visits.clone()),
+      Some(q) if visits[cave] < q => {
+        let mut next = visits.clone();
+        next[cave] += 1;
+        Some(next)
+      },
+      _ => None
+    }
+  }
+}
+
+// bookkeeping for `count_from`
+struct Search<S> {
+  memo: HashMap<(usize, S), usize>,
+  // states on the path currently being explored
+  open: HashSet<(usize, S)>,
+  // open states that were reached again
+  looped: HashSet<(usize, S)>
+}
+
+struct Frame<S> {
+  at: usize,
+  // index of the next neighbour of `at` to try
+  next: usize,
+  state: S
+}
+
+// depth first walk over cave paths, one stack frame per cave on the current path
+struct Paths<'a, P: VisitPolicy> {
+  caves: &'a Caves,
+  policy: &'a P,
+  to: usize,
+  stack: Vec<Frame<P::State>>
+}
+
+impl<'a, P: VisitPolicy> Iterator for Paths<'a, P> {
+  type Item = Vec<&'a str>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while let Some(top) = self.stack.last_mut() {
+      if top.at == self.to {
+        let path = self.stack.iter().map(|f| self.caves.name(f.at)).collect();
+        self.stack.pop();
+        return Some(path);
+      }
+
+      let Some(&next) = self.caves.adjacency[top.at].get(top.next) else {
+        self.stack.pop();
+        continue;
+      };
+      top.next += 1;
+
+      // a state already on the stack starts a loop, which `paths` has
+      // checked can never reach the end
+      if let Some(state) = self.policy.enter(self.caves, &top.state, next) {
+        if !self.stack.iter().any(|f| f.at == next && f.state == state) {
+          self.stack.push(Frame { at: next, next: 0, state });
+        }
+      }
+    }
+
+    None
   }
 }
 
@@ -110,25 +382,143 @@ mod tests {
   #[test]
   fn part1_example() {
     let c : Caves = SAMPLE.parse().expect("shit");
-    assert_eq!(c.total_paths(), 10);
+    assert_eq!(c.total_paths(), Ok(10));
   }
 
   #[test]
   fn part1_solution() {
     let c : Caves = INPUT.parse().expect("shit");
-    assert_eq!(c.total_paths(), 5576);
+    assert_eq!(c.total_paths(), Ok(5576));
   }
 
   #[test]
   fn part2_example() {
     let c : Caves = SAMPLE.parse().expect("shit");
-    assert_eq!(c.total_paths_2(), 36);
+    assert_eq!(c.total_paths_2(), Ok(36));
 
   }
 
   #[test]
   fn part2_solution() {
     let c : Caves = INPUT.parse().expect("shit");
-    assert_eq!(c.total_paths_2(), 152837);
+    assert_eq!(c.total_paths_2(), Ok(152837));
+  }
+
+  #[test]
+  fn test_many_small_caves() {
+    // a chain of 30 small caves, each link either direct or through a big cave
+    let mut edges = vec!["start-c0".to_owned(), "c29-end".to_owned()];
+    for i in 0..29 {
+      edges.push(format!("c{}-c{}", i, i + 1));
+      edges.push(format!("c{}-B{}", i, i));
+      edges.push(format!("B{}-c{}", i, i + 1));
+    }
+    let c : Caves = edges.join("\n").parse().expect("shit");
+
+    assert_eq!(c.total_paths(), Ok(1 << 29));
+    assert!(c.total_paths_2().expect("shit") > 1 << 29);
+
+    let too_many = (0..70).map(|i| format!("start-c{}", i)).collect::<Vec<_>>().join("\n");
+    assert!(too_many.parse::<Caves>().is_err());
+  }
+
+  #[test]
+  fn test_adjacent_big_caves() {
+    let c : Caves = "start-A\nA-B\nB-end\nA-d".parse().expect("shit");
+
+    // bouncing between A and B never ends without a quota on them
+    assert_eq!(c.total_paths(), Err(INFINITE));
+    assert!(c.paths("start", "end", &Quotas::new()).is_err());
+    assert_eq!(c.count_paths("start", "end", &Quotas::new().limit("d", 2)), Err(INFINITE));
+
+    let quotas = Quotas::new().limit("A", 2).limit("B", 2);
+    assert_eq!(
+      c.listed_paths(&quotas, None, |_| true),
+      Ok(vec!["start,A,B,A,B,end", "start,A,B,end", "start,A,d,A,B,end"].into_iter().map(String::from).collect())
+    );
+    assert_eq!(c.count_paths("start", "end", &quotas), Ok(3));
+
+    // a loop that never leads to the end is harmless
+    let dead_end : Caves = "start-a\na-end\nstart-C\nC-D".parse().expect("shit");
+    assert_eq!(dead_end.total_paths(), Ok(1));
+    assert_eq!(dead_end.paths("start", "end", &SmallRevisits::new(0)).expect("shit").count(), 1);
+
+    // quotas go well past what a byte can count
+    let c : Caves = "start-A\nA-B\nB-end".parse().expect("shit");
+    let generous = Quotas::new().limit("A", 300).limit("B", 300);
+    assert_eq!(c.count_paths("start", "end", &generous), Ok(300));
+    assert_eq!(c.paths("start", "end", &generous).expect("shit").map(|p| p.len()).max(), Some(602));
+  }
+
+  #[test]
+  fn test_listed_paths() {
+    let c : Caves = SAMPLE.parse().expect("shit");
+
+    assert_eq!(c.listed_paths(&SmallRevisits::new(0), None, |_| true).expect("shit"), vec![
+      "start,A,b,A,c,A,end",
+      "start,A,b,A,end",
+      "start,A,b,end",
+      "start,A,c,A,b,A,end",
+      "start,A,c,A,b,end",
+      "start,A,c,A,end",
+      "start,A,end",
+      "start,b,A,c,A,end",
+      "start,b,A,end",
+      "start,b,end",
+    ]);
+    assert_eq!(c.paths("start", "end", &SmallRevisits::new(1)).expect("shit").count(), c.total_paths_2().expect("shit"));
+    assert_eq!(c.listed_paths(&SmallRevisits::new(1), Some(3), |_| true).expect("shit").len(), 3);
+    assert_eq!(c.listed_paths(&SmallRevisits::new(0), None, |p| p.len() <= 3).expect("shit"), vec!["start,A,end", "start,b,end"]);
+    assert_eq!(c.listed_paths(&SmallRevisits::new(1), None, |p| p.contains(&"d")).expect("shit"), vec![
+      "start,A,b,d,b,A,c,A,end",
+      "start,A,b,d,b,A,end",
+      "start,A,b,d,b,end",
+      "start,A,c,A,b,d,b,A,end",
+      "start,A,c,A,b,d,b,end",
+      "start,b,d,b,A,c,A,end",
+      "start,b,d,b,A,end",
+      "start,b,d,b,end",
+    ]);
+
+    let input : Caves = INPUT.parse().expect("shit");
+    assert_eq!(input.paths("start", "end", &SmallRevisits::new(0)).expect("shit").count(), 5576);
+    assert_eq!(input.paths("start", "nowhere", &SmallRevisits::new(0)).expect("shit").count(), 0);
+  }
+
+  #[test]
+  fn test_policies() {
+    let c : Caves = SAMPLE.parse().expect("shit");
+
+    // part 1 is a quota of one visit per small cave
+    assert_eq!(c.count_paths("start", "end", &Quotas::new()), Ok(10));
+
+    let two = SmallRevisits::new(2);
+    assert!(c.count_paths("start", "end", &two).expect("shit") > 36);
+    assert_eq!(c.count_paths("start", "end", &two).expect("shit"), c.paths("start", "end", &two).expect("shit").count());
+
+    let no_b = Quotas::new().forbid("b");
+    assert_eq!(c.listed_paths(&no_b, None, |_| true).expect("shit"), vec!["start,A,c,A,end", "start,A,end"]);
+
+    let quotas = Quotas::new().limit("b", 2).limit("A", 2);
+    assert!(c.paths("start", "end", &quotas).expect("shit").all(|p| {
+      p.iter().filter(|&&cave| cave == "b").count() <= 2 && p.iter().filter(|&&cave| cave == "A").count() <= 2
+    }));
+    assert_eq!(c.count_paths("start", "end", &quotas).expect("shit"), c.paths("start", "end", &quotas).expect("shit").count());
+  }
+
+  #[test]
+  fn test_to_dot() {
+    let c : Caves = "start-A\nA-b\nb-end".parse().expect("shit");
+
+    assert_eq!(
+      c.to_dot(None),
+      "graph caves {\n  \"A\" [shape=box];\n  \"b\" [shape=ellipse];\n  \"end\" [shape=ellipse];\n  \"start\" [shape=ellipse];\n  \"A\" -- \"b\";\n  \"A\" -- \"start\";\n  \"b\" -- \"end\";\n}\n"
+    );
+
+    let dot = c.to_dot(Some(&["start", "A", "end"]));
+    assert!(dot.contains("\"A\" [shape=box, color=red, penwidth=2];"));
+    assert!(dot.contains("\"A\" -- \"start\" [color=red, penwidth=2];"));
+    assert!(dot.contains("\"b\" [shape=ellipse];"));
+    assert!(dot.contains("\"A\" -- \"b\";"));
   }
 }
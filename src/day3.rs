@@ -1,6 +1,7 @@
+use std::str::FromStr;
 
-const INPUT : &'static str = include_str!("../inputs/day3.txt");
-const SAMPLE : &[&'static str] = &[
+const INPUT : &str = include_str!("../inputs/day3.txt");
+const SAMPLE : &[&str] = &[
   "00100",
   "11110",
   "10110",
@@ -15,28 +16,87 @@ const SAMPLE : &[&'static str] = &[
   "01010"
 ];
 
-fn input() -> Vec<&'static str> {
-  INPUT.lines().collect()
+// widest report line we can pack into a single integer
+const MAX_WIDTH : usize = 128;
+
+fn input() -> Report {
+  INPUT.parse().expect("Invalid input")
+}
+
+// diagnostic report parsed once into packed integers.  bit positions are
+// numbered from the left, so position 0 is the most significant bit.
+#[derive(Debug, Clone, PartialEq)]
+struct Report {
+  width: usize,
+  values: Vec<u128>
 }
 
-fn bit_tallies(input: &[&str]) -> Vec<[usize; 2]> {
-  let mut tallies : Vec<[usize; 2]> = vec![];
-  for _ in 0..input[0].len() {
-    tallies.push([0, 0]);
+impl FromStr for Report {
+  type Err = &'static str;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Report::from_lines(s.lines().map(|line| line.trim()).filter(|line| !line.is_empty()))
   }
+}
 
-  for line in input {
-    for (index, ch) in line.chars().enumerate() {
-      let zero_or_one : usize = ch.to_digit(10).unwrap() as usize;
-      tallies.get_mut(index).unwrap()[zero_or_one] += 1;
-    }
+impl Report {
+  pub fn from_lines<'a, I>(lines: I) -> Result<Self, &'static str>
+  where
+    I: IntoIterator<Item=&'a str>
+  {
+    let mut width = None;
+    let values = lines
+      .into_iter()
+      .map(|line| {
+        if *width.get_or_insert(line.len()) != line.len() {
+          return Err("Inconsistent line length");
+        }
+        if line.len() > MAX_WIDTH {
+          return Err("Line too wide");
+        }
+
+        line.bytes().try_fold(0u128, |value, b| match b {
+          b'0' => Ok(value << 1),
+          b'1' => Ok((value << 1) | 1),
+          _ => Err("Invalid character")
+        })
+      })
+      .collect::<Result<Vec<u128>, _>>()?;
+
+    Ok(Report { width: width.unwrap_or(0), values })
+  }
+
+  pub fn len(&self) -> usize {
+    self.values.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.values.is_empty()
   }
 
-  tallies
+  // mask selecting the bit at `pos` (counted from the left)
+  fn mask(&self, pos: usize) -> u128 {
+    1 << (self.width - 1 - pos)
+  }
+
+  // count of ones at `pos` across the given values
+  fn ones_at(&self, values: &[u128], pos: usize) -> usize {
+    let mask = self.mask(pos);
+    values.iter().filter(|&&v| v & mask != 0).count()
+  }
 }
 
-fn calc_gamma_epsilon(input: &[&str]) -> (usize, usize) {
-  let tallies = bit_tallies(&input);
+fn bit_tallies(report: &Report) -> Vec<[usize; 2]> {
+  (0..report.width)
+    .map(|pos| {
+      let ones = report.ones_at(&report.values, pos);
+      [report.len() - ones, ones]
+    })
+    .collect()
+}
+
+fn calc_gamma_epsilon(report: &Report) -> (u128, u128) {
+  let tallies = bit_tallies(report);
   let mut gamma = 0;
   let mut epsilon = 0;
 
@@ -53,39 +113,100 @@ fn calc_gamma_epsilon(input: &[&str]) -> (usize, usize) {
   (gamma, epsilon)
 }
 
-fn calc_oxygen(input: &[&str]) -> usize {
-  let mut values : Vec<Vec<char>> = input.iter().map(|s| s.chars().collect()).collect();
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Commonality {
+  Most,
+  Least
+}
 
-  for pos in 0..input[0].len() {
-    let ones = values.iter().filter(|s| s[pos] == '1').count();
-    let zeros = values.len() - ones;
-    let ch = if ones >= zeros { '1' } else { '0' };
-    values = values.iter().filter(|s| s[pos] == ch).cloned().collect();
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum BitOrder {
+  MsbFirst,
+  LsbFirst
+}
 
-    if values.len() == 1 {
-      break;
+// describes which bit survives at each position when filtering for a rating
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Criteria {
+  keep: Commonality,
+  // bit kept when ones and zeros are equally common
+  tie: bool,
+  order: BitOrder
+}
+
+impl Criteria {
+  pub const OXYGEN : Criteria = Criteria { keep: Commonality::Most, tie: true, order: BitOrder::MsbFirst };
+  pub const CO2 : Criteria = Criteria { keep: Commonality::Least, tie: false, order: BitOrder::MsbFirst };
+
+  fn choose(&self, ones: usize, zeros: usize) -> bool {
+    if ones == zeros {
+      return self.tie;
+    }
+    match self.keep {
+      Commonality::Most => ones > zeros,
+      Commonality::Least => ones < zeros
     }
   }
 
-  usize::from_str_radix(&values[0].iter().collect::<String>(), 2).unwrap()
+  fn positions(&self, width: usize) -> Box<dyn Iterator<Item=usize>> {
+    match self.order {
+      BitOrder::MsbFirst => Box::new(0..width),
+      BitOrder::LsbFirst => Box::new((0..width).rev())
+    }
+  }
 }
 
+// one filtering round: the bit kept at `pos` and how many values it removed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Elimination {
+  pos: usize,
+  ones: usize,
+  zeros: usize,
+  kept: bool,
+  eliminated: usize,
+  remaining: usize
+}
 
-fn calc_co2(input: &[&str]) -> usize {
-  let mut values : Vec<Vec<char>> = input.iter().map(|s| s.chars().collect()).collect();
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Rating {
+  value: u128,
+  trace: Vec<Elimination>
+}
 
-  for pos in 0..input[0].len() {
-    let ones = values.iter().filter(|s| s[pos] == '1').count();
-    let zeros = values.len() - ones;
-    let ch = if ones >= zeros { '0' } else { '1' };
-    values = values.iter().filter(|s| s[pos] == ch).cloned().collect();
+fn calc_rating(report: &Report, criteria: &Criteria) -> Option<Rating> {
+  let mut values = report.values.clone();
+  let mut trace = vec![];
 
-    if values.len() == 1 {
+  for pos in criteria.positions(report.width) {
+    if values.len() <= 1 {
       break;
     }
+    let mask = report.mask(pos);
+    let ones = report.ones_at(&values, pos);
+    let zeros = values.len() - ones;
+    let kept = criteria.choose(ones, zeros);
+    let want = if kept { mask } else { 0 };
+
+    values.retain(|&v| v & mask == want);
+    trace.push(Elimination {
+      pos,
+      ones,
+      zeros,
+      kept,
+      eliminated: if kept { zeros } else { ones },
+      remaining: values.len()
+    });
   }
 
-  usize::from_str_radix(&values[0].iter().collect::<String>(), 2).unwrap()
+  values.first().map(|&value| Rating { value, trace })
+}
+
+fn calc_oxygen(report: &Report) -> Option<u128> {
+  calc_rating(report, &Criteria::OXYGEN).map(|r| r.value)
+}
+
+fn calc_co2(report: &Report) -> Option<u128> {
+  calc_rating(report, &Criteria::CO2).map(|r| r.value)
 }
 
 
@@ -93,9 +214,13 @@ fn calc_co2(input: &[&str]) -> usize {
 mod tests {
   use super::*;
 
+  fn sample() -> Report {
+    Report::from_lines(SAMPLE.iter().copied()).expect("shit")
+  }
+
   #[test]
   fn part1_example() {
-    assert_eq!(calc_gamma_epsilon(&SAMPLE), (22, 9));
+    assert_eq!(calc_gamma_epsilon(&sample()), (22, 9));
   }
 
   #[test]
@@ -106,16 +231,43 @@ mod tests {
 
   #[test]
   fn part2_example() {
-    let o = calc_oxygen(&SAMPLE);
-    let co2 = calc_co2(&SAMPLE);
+    let o = calc_oxygen(&sample());
+    let co2 = calc_co2(&sample());
 
-    assert_eq!((o, co2), (23, 10));
+    assert_eq!((o, co2), (Some(23), Some(10)));
   }
 
   #[test]
   fn part2_solution() {
-    let o = calc_oxygen(&input());
-    let co2 = calc_co2(&input());
+    let o = calc_oxygen(&input()).unwrap();
+    let co2 = calc_co2(&input()).unwrap();
     assert_eq!(o * co2, 4672151);
   }
+
+  #[test]
+  fn test_parse() {
+    assert_eq!(Report::from_lines(["101", "10"]), Err("Inconsistent line length"));
+    assert_eq!(Report::from_lines(["102"]), Err("Invalid character"));
+    assert_eq!("".parse::<Report>().map(|r| r.is_empty()), Ok(true));
+
+    let wide = format!("1{}", "0".repeat(127));
+    let r = Report::from_lines([wide.as_str()]).expect("shit");
+    assert_eq!(r.values, vec![1 << 127]);
+    assert_eq!(Report::from_lines([format!("0{}", wide).as_str()]), Err("Line too wide"));
+  }
+
+  #[test]
+  fn test_rating_trace() {
+    let r = calc_rating(&sample(), &Criteria::OXYGEN).expect("shit");
+    assert_eq!(r.value, 23);
+    assert_eq!(
+      r.trace.iter().map(|e| (e.pos, e.kept, e.remaining)).collect::<Vec<_>>(),
+      vec![(0, true, 7), (1, false, 4), (2, true, 3), (3, true, 2), (4, true, 1)]
+    );
+
+    let lsb = Criteria { order: BitOrder::LsbFirst, ..Criteria::CO2 };
+    let r = calc_rating(&sample(), &lsb).expect("shit");
+    assert_eq!(r.trace[0], Elimination { pos: 4, ones: 5, zeros: 7, kept: true, eliminated: 7, remaining: 5 });
+    assert_eq!(r.value, 0b11001);
+  }
 }
@@ -1,28 +1,110 @@
 use std::str::FromStr;
-use std::collections::HashSet;
-use std::num::ParseIntError;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::{Error as IoError, ErrorKind};
 
-const INPUT : &'static str = include_str!("../inputs/day4.txt");
-const SAMPLE : &'static str = include_str!("../inputs/day4.sample.txt");
+const INPUT : &str = include_str!("../inputs/day4.txt");
+const SAMPLE : &str = include_str!("../inputs/day4.sample.txt");
+
+type Cell = (usize, usize);
+
+// a set of cells which, once all drawn, wins the board
+#[derive(Debug, Clone, PartialEq)]
+pub enum WinPattern {
+  Rows,
+  Columns,
+  Diagonals,
+  Corners,
+  Blackout,
+  // custom mask the same shape as the board, `true` cells must all be drawn
+  Mask(Vec<Vec<bool>>)
+}
+
+impl WinPattern {
+  pub const STANDARD : &'static [WinPattern] = &[WinPattern::Rows, WinPattern::Columns];
+
+  // expand this pattern into the cell groups it describes for a board
+  fn groups(&self, width: usize, height: usize) -> Result<Vec<Vec<Cell>>, IoError> {
+    let invalid = |msg| IoError::new(ErrorKind::InvalidData, msg);
+
+    let groups = match self {
+      WinPattern::Rows => (0..height)
+        .map(|row| (0..width).map(|col| (row, col)).collect())
+        .collect(),
+      WinPattern::Columns => (0..width)
+        .map(|col| (0..height).map(|row| (row, col)).collect())
+        .collect(),
+      WinPattern::Diagonals if width == height => vec![
+        (0..width).map(|i| (i, i)).collect(),
+        (0..width).map(|i| (i, width - 1 - i)).collect()
+      ],
+      WinPattern::Diagonals => return Err(invalid("Diagonals require a square board")),
+      WinPattern::Corners => {
+        let mut corners = vec![(0, 0), (0, width - 1), (height - 1, 0), (height - 1, width - 1)];
+        corners.sort();
+        corners.dedup();
+        vec![corners]
+      },
+      WinPattern::Blackout => vec![
+        (0..height).flat_map(|row| (0..width).map(move |col| (row, col))).collect()
+      ],
+      WinPattern::Mask(mask) => {
+        if mask.len() != height || mask.iter().any(|row| row.len() != width) {
+          return Err(invalid("Mask does not match board size"));
+        }
+        let cells : Vec<Cell> = mask
+          .iter()
+          .enumerate()
+          .flat_map(|(row, line)| {
+            line.iter().enumerate().filter(|(_, &set)| set).map(move |(col, _)| (row, col))
+          })
+          .collect();
+
+        if cells.is_empty() {
+          return Err(invalid("Mask has no cells set"));
+        }
+        vec![cells]
+      }
+    };
+
+    Ok(groups)
+  }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 struct Board {
-  numbers: Vec<Vec<usize>>
+  numbers: Vec<Vec<usize>>,
+  // every group of cells that wins the board
+  groups: Vec<Vec<Cell>>
 }
 
 impl Board {
-  pub fn is_won(&self, drawn: &HashSet<usize>) -> bool {
-    self.has_won_row(&drawn) || self.has_won_col(&drawn)
+  pub fn width(&self) -> usize {
+    self.numbers[0].len()
+  }
+
+  pub fn height(&self) -> usize {
+    self.numbers.len()
   }
 
-  fn has_won_row(&self, drawn: &HashSet<usize>) -> bool {
-    self.numbers.iter().any(|row| row.iter().all(|n| drawn.contains(n)))
+  pub fn with_patterns(mut self, patterns: &[WinPattern]) -> Result<Self, IoError> {
+    let (width, height) = (self.width(), self.height());
+
+    self.groups = patterns
+      .iter()
+      .map(|p| p.groups(width, height))
+      .collect::<Result<Vec<_>, _>>()?
+      .into_iter()
+      .flatten()
+      .collect();
+
+    Ok(self)
   }
 
-  fn has_won_col(&self, drawn: &HashSet<usize>) -> bool {
-    (0..self.numbers.len()).any(|index|{
-      (0..self.numbers.len()).all(|n| drawn.contains(&self.numbers[n][index]))
-    })
+  pub fn is_won(&self, drawn: &HashSet<usize>) -> bool {
+    self.groups
+      .iter()
+      .any(|group| group.iter().all(|&(row, col)| drawn.contains(&self.numbers[row][col])))
   }
 
   pub fn score(&self, drawn: &HashSet<usize>, last: usize) -> usize {
@@ -34,54 +116,172 @@ impl Board {
     v * last
   }
 
-  pub fn load_problem(input: &str) -> Result<(Vec<usize>, Vec<Board>), ParseIntError> {
-    let (first, rest) = input.split_once("\n").unwrap();
+  pub fn load_problem(input: &str) -> Result<(Vec<usize>, Vec<Board>), Box<dyn Error>> {
+    Board::load_problem_with(input, WinPattern::STANDARD)
+  }
+
+  pub fn load_problem_with(input: &str, patterns: &[WinPattern]) -> Result<(Vec<usize>, Vec<Board>), Box<dyn Error>> {
+    let (first, rest) = input
+      .split_once('\n')
+      .ok_or(IoError::new(ErrorKind::UnexpectedEof, "No boards"))?;
     let nums : Vec<usize> = first
-      .split(",")
-      .map(|item| item.parse::<usize>())
+      .split(',')
+      .map(|item| item.trim().parse::<usize>())
       .collect::<Result<Vec<_>, _>>()?;
 
     let boards : Vec<Board> = rest
       .trim()
       .split("\n\n")
-      .map(|blob| blob.parse())
-      .collect::<Result<Vec<_>, _>>()?;
+      .map(|blob| Ok(blob.parse::<Board>()?.with_patterns(patterns)?))
+      .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
 
     Ok((nums, boards))
   }
 
-  pub fn calculate_first_winner<'a>(numbers: &[usize], boards: &'a [Board]) -> Option<usize> {
-    let mut drawn  = HashSet::new();
+  pub fn calculate_first_winner(numbers: &[usize], boards: &[Board]) -> Option<usize> {
+    Game::new(numbers, boards).finishing_order().first().map(|win| win.score)
+  }
+
+  pub fn calculate_last_winner(numbers: &[usize], boards: &[Board]) -> Option<usize> {
+    Game::new(numbers, boards).finishing_order().last().map(|win| win.score)
+  }
+}
+
+// a board completing on a particular draw
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Win {
+  board: usize,
+  turn: usize,
+  number: usize,
+  score: usize
+}
 
-    for i in 0..numbers.len() {
-      drawn.insert(numbers[i]);
+// result of a single draw; `wins` holds every board completed by it, in board order
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Draw {
+  turn: usize,
+  number: usize,
+  wins: Vec<Win>
+}
+
+// plays the draws in order, yielding one `Draw` per number called.  marking
+// goes through an index from number to the cells and win groups holding it,
+// so each draw costs time proportional to its occurrences across all boards.
+struct Game<'a> {
+  numbers: &'a [usize],
+  boards: &'a [Board],
+  // number -> board for every cell holding that number
+  cells: HashMap<usize, Vec<usize>>,
+  // number -> (board, group) for every group cell holding that number
+  members: HashMap<usize, Vec<(usize, usize)>>,
+  // per board, per group count of drawn cells
+  hits: Vec<Vec<usize>>,
+  // per board sum of undrawn cells
+  unmarked: Vec<usize>,
+  drawn: HashSet<usize>,
+  won: Vec<bool>,
+  // boards that have not won yet
+  remaining: usize,
+  turn: usize
+}
 
-      if let Some(won) = boards.iter().find(|b| b.is_won(&drawn)) {
-        return Some(won.score(&drawn, numbers[i]));
+impl<'a> Game<'a> {
+  pub fn new(numbers: &'a [usize], boards: &'a [Board]) -> Self {
+    let mut cells : HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut members : HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+
+    for (index, board) in boards.iter().enumerate() {
+      for &n in board.numbers.iter().flatten() {
+        cells.entry(n).or_default().push(index);
       }
-    };
-    None
+      for (group, group_cells) in board.groups.iter().enumerate() {
+        for &(row, col) in group_cells {
+          members.entry(board.numbers[row][col]).or_default().push((index, group));
+        }
+      }
+    }
+
+    Game {
+      numbers,
+      boards,
+      cells,
+      members,
+      hits: boards.iter().map(|b| vec![0; b.groups.len()]).collect(),
+      unmarked: boards.iter().map(|b| b.numbers.iter().flatten().sum()).collect(),
+      drawn: HashSet::new(),
+      won: vec![false; boards.len()],
+      remaining: boards.len(),
+      turn: 0
+    }
   }
 
-  pub fn calculate_last_winner<'a>(numbers: &[usize], boards: &'a [Board]) -> Option<usize> {
-    let mut boards = boards.to_owned();
-    let mut drawn = HashSet::new();
+  pub fn is_finished(&self) -> bool {
+    self.remaining == 0
+  }
+
+  // every winning board in the order it won, stopping once all boards are done
+  pub fn finishing_order(mut self) -> Vec<Win> {
+    let mut order = vec![];
+
+    while !self.is_finished() {
+      match self.next() {
+        Some(draw) => order.extend(draw.wins),
+        None => break
+      }
+    }
+    order
+  }
+
+  // mark `number` everywhere it appears, returning boards that completed a group
+  fn mark(&mut self, number: usize) -> Vec<usize> {
+    if !self.drawn.insert(number) {
+      return vec![];
+    }
 
-    for i in 0..numbers.len() {
-      drawn.insert(numbers[i]);
+    for &board in self.cells.get(&number).into_iter().flatten() {
+      self.unmarked[board] -= number;
+    }
 
-      if boards.len() > 1 {
-        boards.retain(|b| !b.is_won(&drawn));
-      } else if boards[0].is_won(&drawn) {
-          return Some(boards[0].score(&drawn, numbers[i]));
+    let mut completed = vec![];
+    for &(board, group) in self.members.get(&number).into_iter().flatten() {
+      self.hits[board][group] += 1;
+      if self.hits[board][group] == self.boards[board].groups[group].len() && !self.won[board] {
+        completed.push(board);
       }
     }
-    None
+
+    completed.sort_unstable();
+    completed.dedup();
+    completed
+  }
+}
+
+impl<'a> Iterator for Game<'a> {
+  type Item = Draw;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let turn = self.turn;
+    let number = *self.numbers.get(turn)?;
+
+    self.turn += 1;
+
+    let wins = self
+      .mark(number)
+      .into_iter()
+      .map(|board| Win { board, turn, number, score: self.unmarked[board] * number })
+      .collect::<Vec<Win>>();
+
+    for win in &wins {
+      self.won[win.board] = true;
+    }
+    self.remaining -= wins.len();
+
+    Some(Draw { turn, number, wins })
   }
 }
 
 impl FromStr for Board {
-  type Err = ParseIntError;
+  type Err = Box<dyn Error>;
 
   fn from_str(s: &str) -> Result<Self, Self::Err> {
     let parts : Vec<Vec<usize>> = s
@@ -94,7 +294,14 @@ impl FromStr for Board {
       })
       .collect::<Result<Vec<_>,_>>()?;
 
-    Ok(Board { numbers: parts } )
+    if parts.is_empty() || parts[0].is_empty() {
+      return Err(Box::new(IoError::new(ErrorKind::UnexpectedEof, "Empty board")));
+    }
+    if parts.iter().any(|row| row.len() != parts[0].len()) {
+      return Err(Box::new(IoError::new(ErrorKind::InvalidData, "Board is not rectangular")));
+    }
+
+    Ok(Board { numbers: parts, groups: vec![] }.with_patterns(WinPattern::STANDARD)?)
   }
 }
 
@@ -105,7 +312,7 @@ mod tests {
   #[test]
   fn part1_example() {
     let input = SAMPLE;
-    let (nums, boards) = Board::load_problem(&input).expect("shit");
+    let (nums, boards) = Board::load_problem(input).expect("shit");
     let score = Board::calculate_first_winner(&nums, &boards);
 
     assert_eq!(score, Some(4512));
@@ -114,7 +321,7 @@ mod tests {
   #[test]
   fn part1_solution() {
     let input = INPUT;
-    let (nums, boards) = Board::load_problem(&input).expect("shit");
+    let (nums, boards) = Board::load_problem(input).expect("shit");
     let score = Board::calculate_first_winner(&nums, &boards);
 
     assert_eq!(score, Some(89001));
@@ -123,7 +330,7 @@ mod tests {
   #[test]
   fn part2_example() {
     let input = SAMPLE;
-    let (nums, boards) = Board::load_problem(&input).expect("shit");
+    let (nums, boards) = Board::load_problem(input).expect("shit");
     let r = Board::calculate_last_winner(&nums, &boards);
 
     assert_eq!(r, Some(1924));
@@ -132,9 +339,117 @@ mod tests {
   #[test]
   fn part2_solution() {
     let input = INPUT;
-    let (nums, boards) = Board::load_problem(&input).expect("shit");
+    let (nums, boards) = Board::load_problem(input).expect("shit");
     let r = Board::calculate_last_winner(&nums, &boards);
 
     assert_eq!(r, Some(7296));
   }
+
+  #[test]
+  fn test_win_patterns() {
+    let board : Board = "1 2 3\n4 5 6\n7 8 9".parse().expect("shit");
+    let drawn = |nums: &[usize]| nums.iter().copied().collect::<HashSet<usize>>();
+
+    assert!(!board.is_won(&drawn(&[1, 5, 9])));
+    let diag = board.clone().with_patterns(&[WinPattern::Diagonals]).expect("shit");
+    assert!(diag.is_won(&drawn(&[1, 5, 9])));
+    assert!(diag.is_won(&drawn(&[3, 5, 7])));
+
+    let corners = board.clone().with_patterns(&[WinPattern::Corners]).expect("shit");
+    assert!(corners.is_won(&drawn(&[1, 3, 7, 9])));
+    assert!(!corners.is_won(&drawn(&[1, 2, 3])));
+
+    let blackout = board.clone().with_patterns(&[WinPattern::Blackout]).expect("shit");
+    assert!(!blackout.is_won(&drawn(&[1, 2, 3, 4, 5, 6, 7, 8])));
+    assert!(blackout.is_won(&drawn(&[1, 2, 3, 4, 5, 6, 7, 8, 9])));
+
+    let plus = vec![vec![false, true, false], vec![true, true, true], vec![false, true, false]];
+    let mask = board.with_patterns(&[WinPattern::Mask(plus)]).expect("shit");
+    assert!(mask.is_won(&drawn(&[2, 4, 5, 6, 8])));
+  }
+
+  #[test]
+  fn test_rectangular_boards() {
+    let board : Board = "1 2 3 4\n5 6 7 8".parse().expect("shit");
+    let drawn : HashSet<usize> = [3, 7].into_iter().collect();
+
+    assert_eq!((board.width(), board.height()), (4, 2));
+    assert!(board.is_won(&drawn));
+    assert!(board.clone().with_patterns(&[WinPattern::Diagonals]).is_err());
+    assert!(board.with_patterns(&[WinPattern::Mask(vec![vec![true; 3]; 2])]).is_err());
+    assert!("1 2 3\n4 5".parse::<Board>().is_err());
+    assert!(Board::load_problem_with("1,2\n\n1 2\n3 4", &[WinPattern::Diagonals]).is_ok());
+    assert!(Board::load_problem_with("1,2\n\n1 2 3\n3 4 5", &[WinPattern::Diagonals]).is_err());
+  }
+
+  #[test]
+  fn test_finishing_order() {
+    let (nums, boards) = Board::load_problem(SAMPLE).expect("shit");
+    let order = Game::new(&nums, &boards).finishing_order();
+
+    assert_eq!(
+      order.iter().map(|w| (w.board, w.turn, w.number)).collect::<Vec<_>>(),
+      vec![(2, 11, 24), (0, 13, 16), (1, 14, 13)]
+    );
+    assert_eq!(order[0].score, 4512);
+    assert_eq!(order[2].score, 1924);
+
+    let events : Vec<Draw> = Game::new(&nums, &boards).take(12).collect();
+    assert_eq!(events.len(), 12);
+    assert!(events[..11].iter().all(|d| d.wins.is_empty()));
+    assert_eq!(events[11].wins.len(), 1);
+  }
+
+  #[test]
+  fn test_tied_winners() {
+    let (nums, boards) = Board::load_problem("1,2,3\n\n1 2\n5 6\n\n9 8\n7 3\n\n2 1\n4 4").expect("shit");
+    let draws : Vec<Draw> = Game::new(&nums, &boards).collect();
+
+    assert_eq!(draws.len(), 3);
+
+    let mut game = Game::new(&nums, &boards);
+    game.next();
+    game.next();
+    assert_eq!(game.remaining, 1);
+    assert!(!game.is_finished());
+    assert_eq!(
+      draws[1].wins.iter().map(|w| (w.board, w.score)).collect::<Vec<_>>(),
+      vec![(0, 22), (2, 16)]
+    );
+    assert!(draws[2].wins.is_empty());
+  }
+
+  #[test]
+  fn test_indexed_matches_scan() {
+    // deterministic pseudo random boards with repeated numbers across boards
+    let mut seed : usize = 12345;
+    let mut rand = |m: usize| {
+      seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+      (seed >> 33) % m
+    };
+
+    let boards : Vec<Board> = (0..500)
+      .map(|_| {
+        let numbers = (0..5).map(|_| (0..5).map(|_| rand(100)).collect()).collect();
+        Board { numbers, groups: vec![] }.with_patterns(&[WinPattern::Rows, WinPattern::Columns, WinPattern::Diagonals]).expect("shit")
+      })
+      .collect();
+    let nums : Vec<usize> = (0..300).map(|_| rand(100)).collect();
+
+    let order = Game::new(&nums, &boards).finishing_order();
+    assert_eq!(order.len(), boards.len());
+
+    let mut drawn = HashSet::new();
+    for win in order {
+      drawn.extend(nums[..=win.turn].iter().copied());
+      let board = &boards[win.board];
+      assert!(board.is_won(&drawn));
+      assert_eq!(win.score, board.score(&drawn, win.number));
+
+      drawn.clear();
+      drawn.extend(nums[..win.turn].iter().copied());
+      assert!(!board.is_won(&drawn));
+      drawn.clear();
+    }
+  }
 }
@@ -1,11 +1,12 @@
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::str::FromStr;
+use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::error::Error;
 use std::io::{Error as IoError, ErrorKind};
 
-const INPUT : &'static str = include_str!("../inputs/day5.txt");
-const SAMPLE : &'static str = include_str!("../inputs/day5.sample.txt");
+const INPUT : &str = include_str!("../inputs/day5.txt");
+const SAMPLE : &str = include_str!("../inputs/day5.sample.txt");
 
 fn input(s: &'static str) -> Result<Vec<Line>, Box<dyn Error>> {
   s
@@ -46,7 +47,10 @@ struct Line {
 pub enum Orientation {
   Horizontal,
   Vertical,
-  Diagonal
+  // exactly 45 degrees
+  Diagonal,
+  // any other slope
+  Sloped
 }
 
 impl FromStr for Line {
@@ -70,48 +74,380 @@ impl Line {
     match (self.p1.x.cmp(&self.p2.x), self.p1.y.cmp(&self.p2.y)) {
       (Ordering::Equal, _) => Orientation::Vertical,
       (_, Ordering::Equal) => Orientation::Horizontal,
-      (_, _) => Orientation::Diagonal
+      _ if (self.p2.x - self.p1.x).abs() == (self.p2.y - self.p1.y).abs() => Orientation::Diagonal,
+      _ => Orientation::Sloped
     }
   }
 
+  // family of parallel lines this one belongs to, if it is axis aligned or 45 degrees
+  fn family(&self) -> Option<Family> {
+    let dx = self.p2.x - self.p1.x;
+    let dy = self.p2.y - self.p1.y;
+
+    match (dx, dy) {
+      (_, 0) => Some(Family::Horizontal),
+      (0, _) => Some(Family::Vertical),
+      _ if dx == dy => Some(Family::Diagonal),
+      _ if dx == -dy => Some(Family::AntiDiagonal),
+      _ => None
+    }
+  }
+
+  // cells the line passes through.  steps one cell at a time along the
+  // longer axis, rounding the other axis to the nearest cell (ties round up).
+  // endpoints are ordered first so a line rasterizes the same in both directions.
   fn points(&self) -> HashSet<Point> {
-    let xstep = (self.p2.x - self.p1.x).signum();
-    let ystep = (self.p2.y - self.p1.y).signum();
+    let (from, to) = if (self.p1.x, self.p1.y) <= (self.p2.x, self.p2.y) {
+      (self.p1, self.p2)
+    } else {
+      (self.p2, self.p1)
+    };
+    let dx = to.x - from.x;
+    let dy = to.y - from.y;
+    let steps = dx.abs().max(dy.abs());
+
+    if steps == 0 {
+      return [from].into_iter().collect();
+    }
+
+    // from + round(i * delta / steps)
+    let offset = |i: isize, delta: isize| (2 * i * delta + steps).div_euclid(2 * steps);
+
+    (0..=steps)
+      .map(|i| Point { x: from.x + offset(i, dx), y: from.y + offset(i, dy) })
+      .collect()
+  }
+
+  fn intersection_count(lines: &[Line]) -> usize {
+    let overlaps = Overlaps::new(lines);
+    let mut count = overlaps.count();
+
+    // lines the span geometry can't describe are enumerated cell by cell
+    let mut extra : HashMap<Point, usize> = HashMap::new();
+    for line in lines.iter().filter(|line| line.family().is_none()) {
+      for p in line.points() {
+        *extra.entry(p).or_insert(0) += 1;
+      }
+    }
+
+    for (p, n) in extra {
+      let covered = Family::ALL.iter().map(|&f| overlaps.coverage(f, p)).collect::<Vec<usize>>();
+      let already = covered.iter().any(|&c| c >= 2) || covered.iter().filter(|&&c| c > 0).count() >= 2;
+
+      if !already && n + covered.iter().sum::<usize>() >= 2 {
+        count += 1;
+      }
+    }
 
-    let mut results = HashSet::new();
-    let mut p = self.p1;
+    count
+  }
+}
 
-    while p != self.p2 {
-      results.insert(p);
+// a family of parallel lines, each member being `a * x + b * y = key`.
+// points along a member are addressed by `param` (x, or y for verticals).
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum Family {
+  Horizontal,
+  Vertical,
+  Diagonal,
+  AntiDiagonal
+}
 
-      p.x += xstep;
-      p.y += ystep;
+impl Family {
+  const ALL : [Family; 4] = [Family::Horizontal, Family::Vertical, Family::Diagonal, Family::AntiDiagonal];
+
+  fn coefficients(&self) -> (isize, isize) {
+    match self {
+      Family::Horizontal => (0, 1),
+      Family::Vertical => (1, 0),
+      Family::Diagonal => (-1, 1),
+      Family::AntiDiagonal => (1, 1)
     }
-    results.insert(self.p2);
-    results
   }
 
+  fn key(&self, p: Point) -> isize {
+    let (a, b) = self.coefficients();
+    a * p.x + b * p.y
+  }
 
-  fn intersection_count(lines: &[Line]) -> usize {
-    let mut results : HashSet<Point> = HashSet::new();
+  fn param(&self, p: Point) -> isize {
+    match self {
+      Family::Vertical => p.y,
+      _ => p.x
+    }
+  }
+
+  fn point(&self, key: isize, param: isize) -> Point {
+    match self {
+      Family::Horizontal => Point { x: param, y: key },
+      Family::Vertical => Point { x: key, y: param },
+      Family::Diagonal => Point { x: param, y: key + param },
+      Family::AntiDiagonal => Point { x: param, y: key - param }
+    }
+  }
+
+  // the grid point where member `k1` of this family crosses member `k2` of `other`
+  fn crossing(&self, k1: isize, other: Family, k2: isize) -> Option<Point> {
+    let (a1, b1) = self.coefficients();
+    let (a2, b2) = other.coefficients();
+    let det = a1 * b2 - a2 * b1;
+    let x = k1 * b2 - k2 * b1;
+    let y = a1 * k2 - a2 * k1;
+
+    if det == 0 || x % det != 0 || y % det != 0 {
+      return None;
+    }
+    Some(Point { x: x / det, y: y / det })
+  }
+}
+
+// maximal run of cells along one line covered once, or more than once
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Span {
+  lo: isize,
+  hi: isize,
+  multi: bool
+}
+
+impl Span {
+  fn len(&self) -> usize {
+    (self.hi - self.lo + 1) as usize
+  }
+}
+
+// merged coverage of all aligned lines: per family, per member line, the
+// sorted disjoint spans covered by at least one vent
+struct Overlaps {
+  spans: HashMap<Family, BTreeMap<isize, Vec<Span>>>
+}
+
+impl Overlaps {
+  fn new(lines: &[Line]) -> Self {
+    let mut events : HashMap<Family, BTreeMap<isize, Vec<(isize, isize)>>> = HashMap::new();
+
+    for line in lines {
+      if let Some(f) = line.family() {
+        let (a, b) = (f.param(line.p1), f.param(line.p2));
+        let list = events.entry(f).or_default().entry(f.key(line.p1)).or_default();
+        list.push((a.min(b), 1));
+        list.push((a.max(b) + 1, -1));
+      }
+    }
+
+    let spans = events
+      .into_iter()
+      .map(|(f, keys)| {
+        let keys = keys
+          .into_iter()
+          .map(|(key, mut list)| {
+            list.sort_unstable();
+            (key, Overlaps::sweep(&list))
+          })
+          .collect();
+        (f, keys)
+      })
+      .collect();
+
+    Overlaps { spans }
+  }
+
+  // turn sorted +1/-1 boundary events into spans of coverage
+  fn sweep(events: &[(isize, isize)]) -> Vec<Span> {
+    let mut spans : Vec<Span> = vec![];
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < events.len() {
+      let at = events[i].0;
+      while i < events.len() && events[i].0 == at {
+        depth += events[i].1;
+        i += 1;
+      }
+
+      // close the previous span at this boundary
+      if let Some(last) = spans.last_mut() {
+        if last.hi == isize::MAX {
+          last.hi = at - 1;
+        }
+      }
+
+      if depth > 0 {
+        let multi = depth > 1;
+        match spans.last_mut() {
+          Some(last) if last.multi == multi && last.hi == at - 1 => last.hi = isize::MAX,
+          _ => spans.push(Span { lo: at, hi: isize::MAX, multi })
+        }
+      }
+    }
+
+    spans
+  }
+
+  // 0 if uncovered, 1 if covered once, 2 if covered more than once
+  fn coverage(&self, f: Family, p: Point) -> usize {
+    let param = f.param(p);
+
+    self.spans
+      .get(&f)
+      .and_then(|keys| keys.get(&f.key(p)))
+      .and_then(|spans| {
+        let idx = spans.partition_point(|s| s.hi < param);
+        spans.get(idx).filter(|s| s.lo <= param)
+      })
+      .map_or(0, |s| if s.multi { 2 } else { 1 })
+  }
+
+  // points covered by members of two different families
+  fn crossings(&self) -> HashSet<Point> {
+    let mut crossings = HashSet::new();
+
+    for (i, &f) in Family::ALL.iter().enumerate() {
+      for &g in &Family::ALL[i + 1..] {
+        let (Some(fs), Some(gs)) = (self.spans.get(&f), self.spans.get(&g)) else { continue };
+
+        for (&k1, spans) in fs {
+          for span in spans {
+            let e1 = g.key(f.point(k1, span.lo));
+            let e2 = g.key(f.point(k1, span.hi));
 
-    let sets = lines
-      .iter()
-      .map(|line| line.points())
-      .collect::<Vec<HashSet<Point>>>();
+            for &k2 in gs.range(e1.min(e2)..=e1.max(e2)).map(|(k, _)| k) {
+              let crossing = f
+                .crossing(k1, g, k2)
+                .filter(|&p| (span.lo..=span.hi).contains(&f.param(p)))
+                .filter(|&p| self.coverage(g, p) > 0);
 
-    for i in 0..(sets.len() - 1) {
-      for j in (i + 1)..sets.len() {
-        let points = sets[i].intersection(&sets[j]);
-        results.extend(points);
+              crossings.extend(crossing);
+            }
+          }
+        }
       }
     }
 
-    results.len()
+    crossings
+  }
+
+  // number of cells covered by at least two vents
+  fn count(&self) -> usize {
+    let multi : usize = self.spans
+      .values()
+      .flat_map(|keys| keys.values().flatten())
+      .filter(|s| s.multi)
+      .map(|s| s.len())
+      .sum();
+
+    // each crossing point was counted once per family it is a multi span in
+    self.crossings()
+      .into_iter()
+      .map(|p| Family::ALL.iter().filter(|&&f| self.coverage(f, p) == 2).count())
+      .fold(multi, |total, counted| total + 1 - counted)
   }
 }
 
 
+// per cell vent counts over the bounding box of a set of lines
+struct Heatmap {
+  min: Point,
+  width: usize,
+  height: usize,
+  counts: HashMap<Point, usize>
+}
+
+impl Heatmap {
+  pub fn new(lines: &[Line]) -> Self {
+    let mut counts : HashMap<Point, usize> = HashMap::new();
+    for line in lines {
+      for p in line.points() {
+        *counts.entry(p).or_insert(0) += 1;
+      }
+    }
+
+    let min_x = counts.keys().map(|p| p.x).min().unwrap_or(0);
+    let max_x = counts.keys().map(|p| p.x).max().unwrap_or(-1);
+    let min_y = counts.keys().map(|p| p.y).min().unwrap_or(0);
+    let max_y = counts.keys().map(|p| p.y).max().unwrap_or(-1);
+
+    Heatmap {
+      min: Point { x: min_x, y: min_y },
+      width: (max_x - min_x + 1) as usize,
+      height: (max_y - min_y + 1) as usize,
+      counts
+    }
+  }
+
+  pub fn count_at(&self, p: &Point) -> usize {
+    self.counts.get(p).copied().unwrap_or(0)
+  }
+
+  pub fn max_count(&self) -> usize {
+    self.counts.values().copied().max().unwrap_or(0)
+  }
+
+  fn rows(&self) -> impl Iterator<Item=Vec<usize>> + '_ {
+    (0..self.height).map(move |dy| {
+      (0..self.width)
+        .map(|dx| self.count_at(&Point { x: self.min.x + dx as isize, y: self.min.y + dy as isize }))
+        .collect()
+    })
+  }
+
+  pub fn to_csv(&self) -> String {
+    self
+      .rows()
+      .map(|row| row.iter().map(|n| n.to_string()).collect::<Vec<String>>().join(","))
+      .map(|line| line + "\n")
+      .collect()
+  }
+
+  // plain (ascii) greyscale image, brighter cells have more vents
+  pub fn to_pgm(&self) -> String {
+    let mut out = format!("P2\n{} {}\n{}\n", self.width, self.height, self.max_count().max(1));
+
+    for row in self.rows() {
+      out += &row.iter().map(|n| n.to_string()).collect::<Vec<String>>().join(" ");
+      out.push('\n');
+    }
+    out
+  }
+
+  // plain (ascii) colour image ramping black -> red -> yellow -> white
+  pub fn to_ppm(&self) -> String {
+    let max = self.max_count().max(1);
+    let mut out = format!("P3\n{} {}\n255\n", self.width, self.height);
+
+    for row in self.rows() {
+      let pixels = row
+        .iter()
+        .map(|&n| {
+          let level = n * 765 / max;
+          let (r, g, b) = (level.min(255), level.saturating_sub(255).min(255), level.saturating_sub(510));
+          format!("{} {} {}", r, g, b)
+        })
+        .collect::<Vec<String>>();
+
+      out += &pixels.join(" ");
+      out.push('\n');
+    }
+    out
+  }
+}
+
+// the puzzle's diagram: `.` for empty cells, otherwise the vent count
+impl Display for Heatmap {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    for row in self.rows() {
+      for n in row {
+        match n {
+          0 => write!(f, ".")?,
+          1..=9 => write!(f, "{}", n)?,
+          _ => write!(f, "+")?
+        }
+      }
+      writeln!(f)?;
+    }
+    Ok(())
+  }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -120,7 +456,7 @@ use super::*;
   #[test]
   fn part1_example() {
     let mut lines = input(SAMPLE).expect("shit");
-    lines.retain(|line| line.orientation() != Orientation::Diagonal);
+    lines.retain(|line| matches!(line.orientation(), Orientation::Horizontal | Orientation::Vertical));
 
     assert_eq!(Line::intersection_count(&lines), 5);
   }
@@ -128,7 +464,7 @@ use super::*;
   #[test]
   fn part1_solution() {
     let mut lines = input(INPUT).expect("shit");
-    lines.retain(|line| line.orientation() != Orientation::Diagonal);
+    lines.retain(|line| matches!(line.orientation(), Orientation::Horizontal | Orientation::Vertical));
 
     assert_eq!(Line::intersection_count(&lines), 5147);
   }
@@ -144,4 +480,123 @@ use super::*;
     let lines = input(INPUT).expect("shit");
     assert_eq!(Line::intersection_count(&lines), 16925);
   }
+
+  // count cells on two or more lines by enumerating every point
+  fn brute_force(lines: &[Line]) -> usize {
+    let mut counts : HashMap<Point, usize> = HashMap::new();
+    for line in lines {
+      for p in line.points() {
+        *counts.entry(p).or_insert(0) += 1;
+      }
+    }
+    counts.values().filter(|&&n| n >= 2).count()
+  }
+
+  #[test]
+  fn test_matches_brute_force() {
+    let mut seed : isize = 42;
+    let mut rand = |m: isize| {
+      seed = (seed * 1103515245 + 12345) & 0x7fffffff;
+      (seed >> 8) % m
+    };
+
+    for _ in 0..20 {
+      let lines : Vec<Line> = (0..60)
+        .map(|_| {
+          let p1 = Point { x: rand(40), y: rand(40) };
+          let len = rand(25);
+          let (dx, dy) = [(1, 0), (0, 1), (1, 1), (1, -1), (-1, 0), (0, -1), (-1, -1), (-1, 1)][rand(8) as usize];
+          Line { p1, p2: Point { x: p1.x + dx * len, y: p1.y + dy * len } }
+        })
+        .collect();
+
+      assert_eq!(Line::intersection_count(&lines), brute_force(&lines));
+    }
+  }
+
+  #[test]
+  fn test_huge_coordinates() {
+    let lines : Vec<Line> = [
+      "0,0 -> 1000000000,0",
+      "1500000000,0 -> 500000000,0",
+      "700000000,-5 -> 700000000,5",
+      "0,1000 -> 2000,1000",
+      "1000,0 -> 1000,2000",
+      "0,0 -> 2000,2000",
+      "2000,0 -> 0,2000",
+    ].iter().map(|l| l.parse()).collect::<Result<_, _>>().expect("shit");
+
+    // the overlapping run, where the smaller lines start on or cross y = 0,
+    // and the 4 lines through (1000, 1000)
+    assert_eq!(Line::intersection_count(&lines), 500000001 + 3 + 1);
+  }
+
+  #[test]
+  fn test_sloped_lines() {
+    let line : Line = "0,0 -> 4,2".parse().expect("shit");
+    let reversed : Line = "4,2 -> 0,0".parse().expect("shit");
+    let expected : HashSet<Point> = [(0, 0), (1, 1), (2, 1), (3, 2), (4, 2)]
+      .into_iter()
+      .map(|(x, y)| Point { x, y })
+      .collect();
+
+    assert_eq!(line.orientation(), Orientation::Sloped);
+    assert_eq!(line.points(), expected);
+    assert_eq!(reversed.points(), expected);
+
+    let steep : Line = "3,9 -> 1,0".parse().expect("shit");
+    assert_eq!(steep.points().len(), 10);
+    assert!(steep.points().contains(&Point { x: 1, y: 0 }));
+    assert!(steep.points().contains(&Point { x: 3, y: 9 }));
+
+    let diagonal : Line = "5,5 -> 8,2".parse().expect("shit");
+    assert_eq!(diagonal.orientation(), Orientation::Diagonal);
+    assert_eq!(diagonal.points().len(), 4);
+  }
+
+  #[test]
+  fn test_sloped_matches_brute_force() {
+    let mut seed : isize = 7;
+    let mut rand = |m: isize| {
+      seed = (seed * 1103515245 + 12345) & 0x7fffffff;
+      (seed >> 8) % m
+    };
+
+    for _ in 0..20 {
+      let lines : Vec<Line> = (0..60)
+        .map(|_| Line {
+          p1: Point { x: rand(30), y: rand(30) },
+          p2: Point { x: rand(30), y: rand(30) }
+        })
+        .collect();
+
+      assert_eq!(Line::intersection_count(&lines), brute_force(&lines));
+    }
+  }
+
+  #[test]
+  fn test_heatmap() {
+    let lines = input(SAMPLE).expect("shit");
+    let map = Heatmap::new(&lines);
+
+    assert_eq!(
+      map.to_string(),
+      "1.1....11.\n\
+       .111...2..\n\
+       ..2.1.111.\n\
+       ...1.2.2..\n\
+       .112313211\n\
+       ...1.2....\n\
+       ..1...1...\n\
+       .1.....1..\n\
+       1.......1.\n\
+       222111....\n"
+    );
+    assert_eq!(map.to_csv().lines().next(), Some("1,0,1,0,0,0,0,1,1,0"));
+    assert_eq!(map.to_pgm().lines().take(4).collect::<Vec<_>>(), vec!["P2", "10 10", "3", "1 0 1 0 0 0 0 1 1 0"]);
+
+    let ppm = map.to_ppm();
+    assert_eq!(ppm.lines().take(3).collect::<Vec<_>>(), vec!["P3", "10 10", "255"]);
+    assert!(ppm.lines().nth(7).unwrap().contains("255 255 255"));
+  }
 }
@@ -1,21 +1,146 @@
-use std::{str::FromStr, num::ParseIntError};
+use std::str::FromStr;
+use std::error::Error;
+use std::io::{Error as IoError, ErrorKind};
 
-const INPUT : &'static str = include_str!("../inputs/day6.txt");
-const SAMPLE : &'static str = "3,4,3,1,2";
+const INPUT : &str = include_str!("../inputs/day6.txt");
+const SAMPLE : &str = "3,4,3,1,2";
 
-#[derive(Debug)]
-struct School {
-  fish: [usize; 9]
+// life cycle of a species: an adult spawns every `spawn_interval` ticks, a
+// newborn waits an extra `newborn_delay` ticks before its first spawn
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Species {
+  spawn_interval: usize,
+  newborn_delay: usize
 }
 
-impl School {
+impl Species {
+  pub const LANTERNFISH : Species = Species { spawn_interval: 7, newborn_delay: 2 };
+
+  pub fn new(spawn_interval: usize, newborn_delay: usize) -> Result<Self, &'static str> {
+    if spawn_interval == 0 {
+      return Err("Spawn interval must be positive");
+    }
+    Ok(Species { spawn_interval, newborn_delay })
+  }
+
+  // number of distinct timer values, 0 through the newborn timer
+  pub fn buckets(&self) -> usize {
+    self.spawn_interval + self.newborn_delay
+  }
+
+  fn reset_timer(&self) -> usize {
+    self.spawn_interval - 1
+  }
+
+  fn newborn_timer(&self) -> usize {
+    self.buckets() - 1
+  }
+}
+
+// count of fish of one species for each timer value
+#[derive(Debug, Clone, PartialEq)]
+struct Population {
+  species: Species,
+  fish: Vec<usize>
+}
+
+impl Population {
+  pub fn new(species: Species) -> Self {
+    Population { species, fish: vec![0; species.buckets()] }
+  }
+
+  pub fn parse(species: Species, s: &str) -> Result<Self, Box<dyn Error>> {
+    s
+      .trim()
+      .split(',')
+      .filter(|ch| !ch.trim().is_empty())
+      .try_fold(Population::new(species), |mut pop, ch| {
+        let timer = ch.trim().parse::<usize>()?;
+        pop.add(timer, 1)?;
+        Ok(pop)
+      })
+  }
+
+  pub fn add(&mut self, timer: usize, count: usize) -> Result<(), IoError> {
+    let bucket = self.fish
+      .get_mut(timer)
+      .ok_or(IoError::new(ErrorKind::InvalidData, "Timer out of range for species"))?;
+
+    *bucket += count;
+    Ok(())
+  }
+
   pub fn tick(&mut self) {
     let zeroes = self.fish[0];
 
-    (1..9).for_each(|index| self.fish[index - 1] = self.fish[index]);
+    // rotating moves the spawning fish to the newborn timer, so only the
+    // parents need moving back to the reset timer
+    self.fish.rotate_left(1);
+
+    self.fish[self.species.reset_timer()] += zeroes;
+  }
+
+  pub fn len(&self) -> usize {
+    self.fish.iter().sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  // timer counts after `generations` ticks
+  fn project(&self, generations: u64, m: &Modulus) -> Result<Vec<u128>, &'static str> {
+    let counts = self.fish
+      .iter()
+      .map(|&n| m.reduce(n as u128))
+      .collect::<Vec<u128>>();
+
+    self.transition().pow(generations, m)?.apply(&counts, m)
+  }
+
+  // one generation as a matrix: column `i` is where a fish with timer `i` ends up
+  fn transition(&self) -> Matrix {
+    let size = self.fish.len();
+    let columns = (0..size)
+      .map(|col| {
+        let mut unit = Population::new(self.species);
+        unit.fish[col] = 1;
+        unit.tick();
+        unit.fish
+      })
+      .collect::<Vec<_>>();
+
+    let rows = (0..size)
+      .map(|row| columns.iter().map(|col| col[row] as u128).collect())
+      .collect();
 
-    self.fish[6] += zeroes;
-    self.fish[8] = zeroes;
+    Matrix { rows }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct School {
+  populations: Vec<Population>
+}
+
+impl School {
+  pub fn new() -> Self {
+    School { populations: vec![] }
+  }
+
+  // add fish of `species`; fish of an already present species join its population
+  pub fn add(&mut self, population: Population) {
+    match self.populations.iter_mut().find(|p| p.species == population.species) {
+      Some(existing) => existing.fish
+        .iter_mut()
+        .zip(population.fish)
+        .for_each(|(a, b)| *a += b),
+      None => self.populations.push(population)
+    }
+  }
+
+  pub fn tick(&mut self) {
+    self.populations.iter_mut().for_each(|p| p.tick());
   }
 
   pub fn run(&mut self, generations: usize) -> &Self {
@@ -24,26 +149,146 @@ impl School {
   }
 
   pub fn len(&self) -> usize {
-    self.fish.iter().sum()
+    self.populations.iter().map(|p| p.len()).sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  // population after `generations` ticks, computed by raising the transition
+  // matrix to that power rather than stepping, so huge generation counts are cheap
+  pub fn project(&self, generations: u64) -> Result<u128, &'static str> {
+    self.project_with(generations, &Modulus::None)
+  }
+
+  // as `project`, but with all arithmetic modulo `modulus`
+  pub fn project_mod(&self, generations: u64, modulus: u128) -> Result<u128, &'static str> {
+    if modulus == 0 {
+      return Err("Modulus must be positive");
+    }
+    self.project_with(generations, &Modulus::Some(modulus))
+  }
+
+  fn project_with(&self, generations: u64, m: &Modulus) -> Result<u128, &'static str> {
+    self.populations
+      .iter()
+      .map(|p| p.project(generations, m))
+      .collect::<Result<Vec<_>, _>>()?
+      .into_iter()
+      .flatten()
+      .try_fold(0u128, |total, n| m.add(total, n))
+  }
+}
+
+const OVERFLOW : &str = "Population overflowed u128";
+
+enum Modulus {
+  None,
+  Some(u128)
+}
+
+impl Modulus {
+  fn reduce(&self, n: u128) -> u128 {
+    match self {
+      Modulus::None => n,
+      Modulus::Some(m) => n % m
+    }
+  }
+
+  fn add(&self, a: u128, b: u128) -> Result<u128, &'static str> {
+    match self {
+      Modulus::None => a.checked_add(b).ok_or(OVERFLOW),
+      // once reduced a, b < m so a + b only overflows when m is near u128::MAX
+      Modulus::Some(m) => {
+        let (a, b) = (self.reduce(a), self.reduce(b));
+        Ok(if a >= m - b { a - (m - b) } else { a + b })
+      }
+    }
+  }
+
+  fn mul(&self, a: u128, b: u128) -> Result<u128, &'static str> {
+    match self {
+      Modulus::None => a.checked_mul(b).ok_or(OVERFLOW),
+      Modulus::Some(_) => {
+        // double and add so intermediates never exceed the modulus
+        let (mut a, mut b, mut result) = (self.reduce(a), b, 0);
+        while b > 0 {
+          if b & 1 == 1 {
+            result = self.add(result, a)?;
+          }
+          a = self.add(a, a)?;
+          b >>= 1;
+        }
+        Ok(result)
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Matrix {
+  rows: Vec<Vec<u128>>
+}
+
+impl Matrix {
+  fn identity(size: usize) -> Self {
+    let rows = (0..size)
+      .map(|r| (0..size).map(|c| if r == c { 1 } else { 0 }).collect())
+      .collect();
+    Matrix { rows }
+  }
+
+  fn mul(&self, other: &Matrix, m: &Modulus) -> Result<Matrix, &'static str> {
+    let size = self.rows.len();
+    let mut rows = vec![vec![0u128; size]; size];
+
+    for (r, row) in rows.iter_mut().enumerate() {
+      for (c, cell) in row.iter_mut().enumerate() {
+        for k in 0..size {
+          *cell = m.add(*cell, m.mul(self.rows[r][k], other.rows[k][c])?)?;
+        }
+      }
+    }
+    Ok(Matrix { rows })
+  }
+
+  fn pow(&self, mut exp: u64, m: &Modulus) -> Result<Matrix, &'static str> {
+    let mut result = Matrix::identity(self.rows.len());
+    let mut base = self.clone();
+
+    while exp > 0 {
+      if exp & 1 == 1 {
+        result = result.mul(&base, m)?;
+      }
+      exp >>= 1;
+      if exp > 0 {
+        base = base.mul(&base, m)?;
+      }
+    }
+    Ok(result)
+  }
+
+  fn apply(&self, v: &[u128], m: &Modulus) -> Result<Vec<u128>, &'static str> {
+    self.rows
+      .iter()
+      .map(|row| {
+        row
+          .iter()
+          .zip(v)
+          .try_fold(0u128, |total, (&a, &b)| m.add(total, m.mul(a, b)?))
+      })
+      .collect()
   }
 }
 
 impl FromStr for School {
-  type Err = ParseIntError;
+  type Err = Box<dyn Error>;
 
   fn from_str(s: &str) -> Result<Self, Self::Err> {
-    s
-      .trim()
-      .split(",")
-      .map(|ch| ch.parse::<usize>())
-      .try_fold([0; 9], |mut counts, digit| {
-        digit
-          .map(|index| {
-            counts[index] += 1;
-            counts
-          })
-      })
-      .map(|fish| School { fish })
+    let mut school = School::new();
+    school.add(Population::parse(Species::LANTERNFISH, s)?);
+    Ok(school)
   }
 }
 
@@ -74,4 +319,79 @@ mod tests {
     let mut school : School = INPUT.parse().expect("shit");
     assert_eq!(school.run(256).len(), 1741362314973);
   }
+
+  #[test]
+  fn test_project() {
+    let school : School = SAMPLE.parse().expect("shit");
+    assert_eq!(school.project(0), Ok(5));
+    assert_eq!(school.project(18), Ok(26));
+    assert_eq!(school.project(256), Ok(26984457539));
+
+    let input : School = INPUT.parse().expect("shit");
+    assert_eq!(input.project(256), Ok(1741362314973));
+    assert_eq!(input.project(1_000_000_000_000), Err(OVERFLOW));
+  }
+
+  #[test]
+  fn test_project_mod() {
+    let school : School = SAMPLE.parse().expect("shit");
+    assert_eq!(school.project_mod(256, 1_000_000_007), Ok(26984457539 % 1_000_000_007));
+    assert_eq!(school.project_mod(256, u128::MAX), Ok(26984457539));
+    assert!(school.project_mod(1_000_000_000_000, 1_000_000_007).is_ok());
+    assert!(school.project_mod(10, 0).is_err());
+    assert_eq!(school.project_mod(256, 1), Ok(0));
+
+    // newborns joining the parents' bucket put a 2 in the transition matrix
+    let mut doubling = School::new();
+    doubling.add(Population::parse(Species::new(3, 0).expect("shit"), "0,1").expect("shit"));
+    assert_eq!(doubling.project_mod(5, 1), Ok(0));
+    assert_eq!(doubling.project_mod(5, 3), Ok(doubling.project(5).expect("shit") % 3));
+    assert_eq!(doubling.project_mod(5, 2), Ok(0));
+  }
+
+  #[test]
+  fn test_species() {
+    // a species spawning every 3 ticks with newborns waiting 1 extra tick
+    let species = Species::new(3, 1).expect("shit");
+    let mut pop = Population::parse(species, "0").expect("shit");
+
+    let sizes = (0..6).map(|_| { pop.tick(); pop.len() }).collect::<Vec<_>>();
+    assert_eq!(sizes, vec![2, 2, 2, 3, 4, 4]);
+    assert!(Population::parse(species, "4").is_err());
+    assert!(Species::new(0, 2).is_err());
+
+    let mut school : School = SAMPLE.parse().expect("shit");
+    school.add(Population::parse(species, "0,1,2,3").expect("shit"));
+    school.add(Population::parse(Species::LANTERNFISH, "3").expect("shit"));
+    assert_eq!(school.populations.len(), 2);
+    assert_eq!(school.len(), 10);
+
+    let projected = school.project(40).expect("shit");
+    assert_eq!(school.run(40).len() as u128, projected);
+  }
+
+  #[test]
+  fn test_species_without_delay() {
+    // newborns start on the same timer as parents, so each fish doubles every 3 ticks
+    let species = Species::new(3, 0).expect("shit");
+    let mut pop = Population::parse(species, "0").expect("shit");
+
+    let sizes = (0..6).map(|_| { pop.tick(); pop.len() }).collect::<Vec<_>>();
+    assert_eq!(sizes, vec![2, 2, 2, 4, 4, 4]);
+
+    let lanternfish = Species::new(7, 0).expect("shit");
+    let mut school = School::new();
+    school.add(Population::parse(lanternfish, "3,4,3,1,2").expect("shit"));
+
+    // per fish simulation to check both the bucket tick and the matrix
+    let mut timers = vec![3, 4, 3, 1, 2];
+    for _ in 0..30 {
+      let spawned = timers.iter().filter(|&&t| t == 0).count();
+      timers = timers.iter().map(|&t| if t == 0 { 6 } else { t - 1 }).collect();
+      timers.extend(std::iter::repeat_n(6, spawned));
+    }
+
+    assert_eq!(school.project(30), Ok(timers.len() as u128));
+    assert_eq!(school.run(30).len(), timers.len());
+  }
 }
@@ -1,34 +1,48 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Error as IoError, ErrorKind};
 use std::num::ParseIntError;
 use std::str::FromStr;
 
-const INPUT: &'static str = include_str!("../inputs/day7.txt");
-const SAMPLE: &'static str = "16,1,2,0,4,2,7,1,2,14";
+const INPUT: &str = include_str!("../inputs/day7.txt");
+const SAMPLE: &str = "16,1,2,0,4,2,7,1,2,14";
 
 struct Crabs {
   crabs: HashMap<isize, isize>,
   median: isize,
 }
 
+// lower median of sorted values.  with an even count every position between
+// the two middle values has the same linear cost, so either one is optimal.
 fn calc_median(values: &[isize]) -> isize {
-  let len = values.len();
-  if len & 1 == 1 {
-    (values[len / 2] + values[len / 2 + 1]) / 2
-  } else {
-    values[len / 2]
-  }
+  values[(values.len() - 1) / 2]
+}
+
+// best meeting position and the total fuel it costs
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Alignment {
+  target: isize,
+  cost: isize
 }
 
 impl FromStr for Crabs {
   type Err = ParseIntError;
 
   fn from_str(s: &str) -> Result<Self, Self::Err> {
-    let mut values: Vec<isize> = s
+    let values: Vec<isize> = s
       .trim()
       .split(",")
       .map(|n| n.parse())
       .collect::<Result<Vec<_>, _>>()?;
 
+    Ok(Crabs::from_positions(values))
+  }
+}
+
+impl Crabs {
+  // expects at least one position
+  pub fn from_positions(mut values: Vec<isize>) -> Self {
     values.sort();
     // p1 lowest cost is the median position
     let median = calc_median(&values);
@@ -38,11 +52,9 @@ impl FromStr for Crabs {
       hash
     });
 
-    Ok(Crabs { crabs, median })
+    Crabs { crabs, median }
   }
-}
 
-impl Crabs {
   pub fn lowest_cost<F>(&self, cost_func: F) -> Option<isize>
   where
     F: Fn(isize, isize) -> isize,
@@ -55,6 +67,56 @@ impl Crabs {
       .min()
   }
 
+  // linear cost is minimised at the median
+  pub fn align_linear(&self) -> Alignment {
+    self.alignment_at(self.median, cost_p1)
+  }
+
+  // triangular cost is minimised within half a step of the mean
+  pub fn align_triangular(&self) -> Alignment {
+    let count : isize = self.crabs.values().sum();
+    let total : isize = self.crabs.iter().map(|(&pos, &n)| pos * n).sum();
+    let mean = total.div_euclid(count);
+
+    (mean - 1..=mean + 1)
+      .map(|target| self.alignment_at(target, cost_p2))
+      .min_by_key(|a| (a.cost, a.target))
+      .unwrap()
+  }
+
+  // ternary search for the cheapest target, valid for any cost function whose
+  // total is convex in the target (e.g. a convex function of distance)
+  pub fn align_convex<F>(&self, cost_func: F) -> Alignment
+  where
+    F: Fn(isize, isize) -> isize,
+  {
+    let mut lo = *self.crabs.keys().min().unwrap();
+    let mut hi = *self.crabs.keys().max().unwrap();
+
+    while hi - lo > 2 {
+      let m1 = lo + (hi - lo) / 3;
+      let m2 = hi - (hi - lo) / 3;
+
+      match self.cost_for_target(m1, &cost_func).cmp(&self.cost_for_target(m2, &cost_func)) {
+        Ordering::Less => hi = m2 - 1,
+        Ordering::Greater => lo = m1 + 1,
+        Ordering::Equal => { lo = m1; hi = m2; }
+      }
+    }
+
+    (lo..=hi)
+      .map(|target| self.alignment_at(target, &cost_func))
+      .min_by_key(|a| (a.cost, a.target))
+      .unwrap()
+  }
+
+  fn alignment_at<F>(&self, target: isize, cost_func: F) -> Alignment
+  where
+    F: Fn(isize, isize) -> isize,
+  {
+    Alignment { target, cost: self.cost_for_target(target, cost_func) }
+  }
+
   fn cost_for_target<F>(&self, target: isize, cost_func: F) -> isize
   where
     F: Fn(isize, isize) -> isize,
@@ -67,11 +129,204 @@ impl Crabs {
   }
 }
 
+fn cost_p1(pos: isize, target: isize) -> isize {
+  (target - pos).abs()
+}
+
 fn cost_p2(pos: isize, target: isize) -> isize {
   let distance = (target - pos).abs();
   (distance * (distance + 1)) / 2
 }
 
+// crabs free to move along every axis of a `D` dimensional grid
+struct Swarm<const D: usize> {
+  crabs: HashMap<[isize; D], isize>,
+}
+
+// best meeting point and the total fuel it costs
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct MultiAlignment<const D: usize, C> {
+  target: [isize; D],
+  cost: C,
+}
+
+// one crab per line, coordinates separated by commas
+impl<const D: usize> FromStr for Swarm<D> {
+  type Err = Box<dyn Error>;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut crabs = HashMap::new();
+
+    for line in s.trim().lines() {
+      let coords = line
+        .split(',')
+        .map(|n| n.trim().parse())
+        .collect::<Result<Vec<isize>, _>>()?;
+      let pos: [isize; D] = coords
+        .try_into()
+        .map_err(|_| IoError::new(ErrorKind::InvalidData, "Wrong number of coordinates"))?;
+
+      *crabs.entry(pos).or_insert(0) += 1;
+    }
+
+    if crabs.is_empty() {
+      return Err(Box::new(IoError::new(ErrorKind::UnexpectedEof, "No crabs")));
+    }
+    Ok(Swarm { crabs })
+  }
+}
+
+impl<const D: usize> Swarm<D> {
+  // crabs projected onto a single axis
+  fn axis(&self, axis: usize) -> Crabs {
+    let values = self
+      .crabs
+      .iter()
+      .flat_map(|(pos, &count)| std::iter::repeat_n(pos[axis], count as usize))
+      .collect();
+
+    Crabs::from_positions(values)
+  }
+
+  // cost applied independently along each axis and summed, so each axis can be
+  // optimised on its own.  `cost_func` must be convex, as for `align_convex`.
+  pub fn align_per_axis<F>(&self, cost_func: F) -> MultiAlignment<D, isize>
+  where
+    F: Fn(isize, isize) -> isize,
+  {
+    let mut target = [0; D];
+    let mut cost = 0;
+
+    for (axis, t) in target.iter_mut().enumerate() {
+      let best = self.axis(axis).align_convex(&cost_func);
+      *t = best.target;
+      cost += best.cost;
+    }
+
+    MultiAlignment { target, cost }
+  }
+
+  // manhattan distance separates into per axis linear costs: the median of each axis
+  pub fn align_manhattan(&self) -> MultiAlignment<D, isize> {
+    let mut target = [0; D];
+    let mut cost = 0;
+
+    for (axis, t) in target.iter_mut().enumerate() {
+      let best = self.axis(axis).align_linear();
+      *t = best.target;
+      cost += best.cost;
+    }
+
+    MultiAlignment { target, cost }
+  }
+
+  pub fn cost_for_target<F>(&self, target: &[isize; D], cost_func: F) -> f64
+  where
+    F: Fn(&[isize; D], &[isize; D]) -> f64,
+  {
+    self
+      .crabs
+      .iter()
+      .map(|(pos, &count)| cost_func(pos, target) * count as f64)
+      .sum()
+  }
+
+  // the point (not necessarily on the grid) minimising the sum of euclidean
+  // distances, found by weiszfeld's iteration
+  pub fn geometric_median(&self) -> [f64; D] {
+    let total: f64 = self.crabs.values().map(|&n| n as f64).sum();
+    let mut guess = [0.0; D];
+    for (pos, &count) in &self.crabs {
+      for axis in 0..D {
+        guess[axis] += pos[axis] as f64 * count as f64 / total;
+      }
+    }
+
+    for _ in 0..1000 {
+      let mut numerator = [0.0; D];
+      let mut denominator = 0.0;
+
+      for (pos, &count) in &self.crabs {
+        let distance = euclidean_f64(pos, &guess);
+        // sitting exactly on a crab; weiszfeld is undefined there, skip it
+        if distance < 1e-9 {
+          continue;
+        }
+        let weight = count as f64 / distance;
+        for axis in 0..D {
+          numerator[axis] += pos[axis] as f64 * weight;
+        }
+        denominator += weight;
+      }
+
+      if denominator == 0.0 {
+        break;
+      }
+      let next = numerator.map(|n| n / denominator);
+      let moved = euclidean_f64_pair(&next, &guess);
+      guess = next;
+
+      if moved < 1e-9 {
+        break;
+      }
+    }
+
+    guess
+  }
+
+  // cheapest grid point for straight line travel: start from the rounded
+  // geometric median and step to cheaper neighbours while there are any
+  pub fn align_euclidean(&self) -> MultiAlignment<D, f64> {
+    let mut target = self.geometric_median().map(|v| v.round() as isize);
+    let mut cost = self.cost_for_target(&target, euclidean);
+
+    loop {
+      let best = Swarm::<D>::neighbors(&target)
+        .map(|p| (self.cost_for_target(&p, euclidean), p))
+        .min_by(|(a, _), (b, _)| a.total_cmp(b));
+
+      match best {
+        Some((c, p)) if c < cost - 1e-9 => {
+          target = p;
+          cost = c;
+        }
+        _ => break,
+      }
+    }
+
+    MultiAlignment { target, cost }
+  }
+
+  fn neighbors(pos: &[isize; D]) -> impl Iterator<Item = [isize; D]> + '_ {
+    (0..3usize.pow(D as u32))
+      .map(move |mut n| {
+        let mut p = *pos;
+        for v in p.iter_mut() {
+          *v += (n % 3) as isize - 1;
+          n /= 3;
+        }
+        p
+      })
+      .filter(move |p| p != pos)
+  }
+}
+
+fn euclidean<const D: usize>(a: &[isize; D], b: &[isize; D]) -> f64 {
+  a.iter()
+    .zip(b)
+    .map(|(&x, &y)| ((x - y) as f64).powi(2))
+    .sum::<f64>()
+    .sqrt()
+}
+
+fn euclidean_f64<const D: usize>(a: &[isize; D], b: &[f64; D]) -> f64 {
+  euclidean_f64_pair(&a.map(|v| v as f64), b)
+}
+
+fn euclidean_f64_pair<const D: usize>(a: &[f64; D], b: &[f64; D]) -> f64 {
+  a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum::<f64>().sqrt()
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -99,4 +354,59 @@ mod tests {
     let c: Crabs = INPUT.parse().expect("shit");
     assert_eq!(c.lowest_cost(cost_p2), Some(98925151));
   }
+
+  #[test]
+  fn test_median() {
+    assert_eq!(calc_median(&[1, 2, 3]), 2);
+    assert_eq!(calc_median(&[1, 2, 3, 4]), 2);
+    assert_eq!(calc_median(&[5]), 5);
+  }
+
+  #[test]
+  fn test_alignment() {
+    let c: Crabs = SAMPLE.parse().expect("shit");
+    assert_eq!(c.align_linear(), Alignment { target: 2, cost: 37 });
+    assert_eq!(c.align_triangular(), Alignment { target: 5, cost: 168 });
+    assert_eq!(c.align_convex(cost_p2), Alignment { target: 5, cost: 168 });
+    assert_eq!(c.align_convex(|p, t| (t - p).pow(2)).cost, c.lowest_cost(|p, t| (t - p).pow(2)).unwrap());
+
+    let c: Crabs = INPUT.parse().expect("shit");
+    assert_eq!(c.align_linear().cost, 343441);
+    assert_eq!(c.align_triangular().cost, 98925151);
+    assert_eq!(c.align_convex(cost_p1).cost, 343441);
+
+    let odd: Crabs = "0,1,10".parse().expect("shit");
+    assert_eq!(odd.align_linear(), Alignment { target: 1, cost: 10 });
+    assert!("".parse::<Crabs>().is_err());
+  }
+
+  #[test]
+  fn test_swarm_2d() {
+    let swarm: Swarm<2> = "0,0\n4,0\n0,4\n4,4\n2,9".parse().expect("shit");
+
+    assert_eq!(swarm.align_manhattan(), MultiAlignment { target: [2, 4], cost: 21 });
+    assert_eq!(swarm.align_per_axis(cost_p1), swarm.align_manhattan());
+    assert_eq!(swarm.align_per_axis(cost_p2).target, [2, 3]);
+
+    let best = swarm.align_euclidean();
+    let brute = (0..=4)
+      .flat_map(|x| (0..=9).map(move |y| [x, y]))
+      .map(|p| swarm.cost_for_target(&p, euclidean))
+      .fold(f64::MAX, f64::min);
+    assert!((best.cost - brute).abs() < 1e-9);
+
+    assert!("1,2,3".parse::<Swarm<2>>().is_err());
+    assert!("".parse::<Swarm<2>>().is_err());
+  }
+
+  #[test]
+  fn test_swarm_3d() {
+    let swarm: Swarm<3> = "0,0,0\n10,0,0\n0,10,0\n0,0,10\n1,1,1".parse().expect("shit");
+
+    assert_eq!(swarm.align_manhattan(), MultiAlignment { target: [0, 0, 0], cost: 33 });
+
+    let median = swarm.geometric_median();
+    assert!(median.iter().all(|&v| v > 0.0 && v < 2.0));
+    assert_eq!(swarm.align_euclidean().target, [1, 1, 1]);
+  }
 }
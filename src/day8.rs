@@ -1,7 +1,7 @@
 use std::{str::FromStr, error::Error, io, collections::{HashMap, HashSet}};
 
-const INPUT: &'static str = include_str!("../inputs/day8.txt");
-const SAMPLE: &'static str = include_str!("../inputs/day8.sample.txt");
+const INPUT: &str = include_str!("../inputs/day8.txt");
+const SAMPLE: &str = include_str!("../inputs/day8.sample.txt");
 
 struct Sample {
   samples: HashSet<String>,
@@ -44,81 +44,224 @@ impl Sample {
       .count()
   }
 
-  fn contains_other(s1: &str, other: &str) -> bool {
-    let one = s1.chars().collect::<HashSet<char>>();
-    let two = other.chars().collect::<HashSet<char>>();
+  // deduce which segment each wire drives on a standard seven segment display
+  fn solve(&self) -> Result<HashMap<char, char>, &'static str> {
+    self.solve_with(&SEVEN_SEGMENT_FONT)
+  }
+
+  // deduce which segment each wire drives, as a map of wire to segment
+  fn solve_with(&self, font: &Font) -> Result<HashMap<char, char>, &'static str> {
+    let patterns = self.samples
+      .iter()
+      .map(|word| font.mask(word))
+      .collect::<Result<Vec<u64>, _>>()?;
+    let shapes = font.glyphs.iter().map(|&(_, shape)| shape).collect::<Vec<u64>>();
 
-    two.iter().all(|item| one.contains(item))
+    let wiring = solve_wiring(&patterns, &shapes, font.segments.len())?;
+
+    Ok(wiring
+      .iter()
+      .enumerate()
+      .map(|(wire, &segment)| (font.segments[wire], font.segments[segment]))
+      .collect())
   }
 
-  fn contains_other_count(s1: &str, other: &str, count: usize) -> bool {
-    let one = s1.chars().collect::<HashSet<char>>();
-    let two = other.chars().collect::<HashSet<char>>();
+  fn decode(&self) -> Result<usize, &'static str> {
+    self
+      .decode_with(&SEVEN_SEGMENT_FONT)?
+      .iter()
+      .try_fold(0usize, |accum, digit| {
+        digit
+          .parse::<usize>()
+          .map(|d| accum * 10 + d)
+          .map_err(|_| "Output is not a valid digit")
+      })
+  }
+
+  // name of the glyph shown by each output
+  fn decode_with<'a>(&self, font: &'a Font) -> Result<Vec<&'a str>, &'static str> {
+    let wiring = self.solve_with(font)?;
 
-    two.iter().filter(|item| one.contains(item)).count() == count
+    self.outputs
+      .iter()
+      .map(|word| {
+        let lit = word
+          .chars()
+          .map(|ch| wiring.get(&ch).copied().ok_or("Unknown wire"))
+          .collect::<Result<String, _>>()?;
+
+        font.glyph(font.mask(&lit)?).ok_or("Output is not a valid glyph")
+      })
+      .collect()
   }
+}
+
+// a display definition: one line naming the segments, then one line per
+// glyph giving its name and the segments it lights.  blank lines and lines
+// starting with `#` are ignored.
+const SEVEN_SEGMENT : &str = "\
+segments abcdefg
+0 abcefg
+1 cf
+2 acdeg
+3 acdfg
+4 bcdf
+5 abdfg
+6 abdefg
+7 acf
+8 abcdefg
+9 abcdfg
+";
+
+lazy_static! {
+  static ref SEVEN_SEGMENT_FONT : Font = SEVEN_SEGMENT.parse().unwrap();
+}
 
+#[derive(Debug, Clone, PartialEq)]
+struct Font {
+  // segment names, also used as the wire names of scrambled signals
+  segments: Vec<char>,
+  glyphs: Vec<(String, u64)>
+}
 
-  fn decode(&self) -> usize {
-    let mut lookup : HashMap<String, u8> = HashMap::new();
-    let mut rev: HashMap<u8, String> = HashMap::new();
+impl FromStr for Font {
+  type Err = &'static str;
 
-    for s in &self.samples {
-      match s.len() {
-        2 => { lookup.insert(s.clone(), 1); rev.insert(1, s.clone()); },
-        3 => { lookup.insert(s.clone(), 7); rev.insert(7, s.clone()); },
-        4 => { lookup.insert(s.clone(), 4); rev.insert(4, s.clone());  },
-        5 => {  /* nop */ }, // [2, 3, 5]
-        6 => {  /* nop */ }, // [0, 6, 9]
-        7 => { lookup.insert(s.clone(), 8); },
-        d => panic!("Impossible input: {}", d)
-      };
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut lines = s
+      .lines()
+      .map(|line| line.trim())
+      .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+    let segments : Vec<char> = lines
+      .next()
+      .and_then(|line| line.strip_prefix("segments"))
+      .ok_or("Font must start with a segments line")?
+      .trim()
+      .chars()
+      .collect();
+
+    if segments.is_empty() || segments.len() > 64 {
+      return Err("Font must have between 1 and 64 segments");
+    }
+    if segments.iter().collect::<HashSet<_>>().len() != segments.len() {
+      return Err("Duplicate segment name");
     }
 
-    let one = rev.get(&1).unwrap();
-    let seven = rev.get(&7).unwrap();
-    let four = rev.get(&4).unwrap();
+    let mut font = Font { segments, glyphs: vec![] };
 
-    self.samples
-      .iter()
-      .filter(|s| s.len() == 5)
-      .for_each(|s| {
-        // if it contains 1 it's 3
-        if Sample::contains_other(s, one) {
-          lookup.insert(s.clone(), 3);
-        }
-        // if it contains 3 out of 4 parts of 4 it's 5
-        else if Sample::contains_other_count(s, four, 3) {
-          lookup.insert(s.clone(), 5);
-        } else {
-          lookup.insert(s.clone(), 2);
-        }
-      });
+    for line in lines {
+      let (name, lit) = line.split_once(char::is_whitespace).ok_or("Glyph has no segments")?;
+      let shape = font.mask(lit.trim())?;
 
-    self.samples
-      .iter()
-      .filter(|s| s.len() == 6)
-      .for_each(|s| {
-        // if it DOESNT contains 7 it's 6
-        if !Sample::contains_other(s, seven) {
-          lookup.insert(s.clone(), 6);
-        }
-        // if it contains 4 it's 9
-        else if Sample::contains_other(s, four) {
-          lookup.insert(s.clone(), 9);
-        }
-        else {
-          lookup.insert(s.clone(), 0);
+      if font.glyphs.iter().any(|(n, s)| n == name || *s == shape) {
+        return Err("Duplicate glyph");
+      }
+      font.glyphs.push((name.to_owned(), shape));
+    }
+
+    if font.glyphs.is_empty() {
+      return Err("Font has no glyphs");
+    }
+    Ok(font)
+  }
+}
+
+impl Font {
+  fn mask(&self, word: &str) -> Result<u64, &'static str> {
+    word.chars().try_fold(0u64, |mask, ch| {
+      self.segments
+        .iter()
+        .position(|&seg| seg == ch)
+        .map(|index| mask | 1 << index)
+        .ok_or("Unknown wire")
+    })
+  }
+
+  fn glyph(&self, shape: u64) -> Option<&str> {
+    self.glyphs.iter().find(|&&(_, s)| s == shape).map(|(name, _)| name.as_str())
+  }
+}
+
+// find the permutation of `size` wires onto segments under which every
+// pattern lights one of `shapes`.  wires start able to drive any segment and
+// are narrowed by propagation, branching only when propagation stalls.
+fn solve_wiring(patterns: &[u64], shapes: &[u64], size: usize) -> Result<Vec<usize>, &'static str> {
+  let full = u64::MAX >> (64 - size);
+  let mut solutions = vec![];
+
+  search(patterns, shapes, vec![full; size], full, &mut solutions);
+
+  match solutions.len() {
+    0 => Err("No consistent wiring"),
+    1 => Ok(solutions.remove(0)),
+    _ => Err("Ambiguous wiring")
+  }
+}
+
+fn search(patterns: &[u64], shapes: &[u64], mut domains: Vec<u64>, full: u64, solutions: &mut Vec<Vec<usize>>) {
+  // two solutions is enough to know the entry is ambiguous
+  if solutions.len() > 1 || !propagate(patterns, shapes, &mut domains, full) {
+    return;
+  }
+
+  let branch = (0..domains.len())
+    .filter(|&w| domains[w].count_ones() > 1)
+    .min_by_key(|&w| domains[w].count_ones());
+
+  match branch {
+    None => solutions.push(domains.iter().map(|d| d.trailing_zeros() as usize).collect()),
+    Some(wire) => {
+      for segment in 0..domains.len() {
+        if domains[wire] & (1 << segment) != 0 {
+          let mut next = domains.clone();
+          next[wire] = 1 << segment;
+          search(patterns, shapes, next, full, solutions);
         }
-      });
+      }
+    }
+  }
+}
 
+// narrow each wire's possible segments until nothing changes.  returns false
+// if some wire or pattern is left with no options.
+fn propagate(patterns: &[u64], shapes: &[u64], domains: &mut [u64], full: u64) -> bool {
+  // segments a wire may drive given whether it is lit in a pattern showing `shape`
+  let allowed = |pattern: u64, wire: usize, shape: u64| {
+    if pattern & (1 << wire) != 0 { shape } else { !shape & full }
+  };
 
-    self.outputs
-      .iter()
-      .map(|s| *lookup.get(s).unwrap())
-      .fold(0usize, |accum, digit| {
-        accum * 10 + (digit as usize)
-      })
+  loop {
+    let before = domains.to_vec();
+
+    for &pattern in patterns {
+      let candidates = shapes
+        .iter()
+        .copied()
+        .filter(|s| s.count_ones() == pattern.count_ones())
+        .filter(|&s| (0..domains.len()).all(|w| domains[w] & allowed(pattern, w, s) != 0))
+        .collect::<Vec<u64>>();
+
+      for (w, domain) in domains.iter_mut().enumerate() {
+        *domain &= candidates.iter().fold(0, |acc, &s| acc | allowed(pattern, w, s));
+      }
+    }
+
+    // each segment is driven by exactly one wire
+    for w in 0..domains.len() {
+      if domains[w].count_ones() == 1 {
+        for other in (0..domains.len()).filter(|&o| o != w) {
+          domains[other] &= !domains[w];
+        }
+      }
+    }
+
+    if domains.contains(&0) {
+      return false;
+    }
+    if domains == before.as_slice() {
+      return true;
+    }
   }
 }
 
@@ -151,7 +294,7 @@ mod tests {
   fn part2_example() {
     let s = SAMPLE.lines().map(|line| line.parse()).collect::<Result<Vec<Sample>, _>>().expect("shit");
 
-    let ans : usize = s.iter().map(|sample| sample.decode()).sum();
+    let ans : usize = s.iter().map(|sample| sample.decode().unwrap()).sum();
     assert_eq!(ans, 61229);
   }
 
@@ -159,7 +302,85 @@ mod tests {
   fn part2_solution() {
     let s = INPUT.lines().map(|line| line.parse()).collect::<Result<Vec<Sample>, _>>().expect("shit");
 
-    let ans : usize = s.iter().map(|sample| sample.decode()).sum();
+    let ans : usize = s.iter().map(|sample| sample.decode().unwrap()).sum();
     assert_eq!(ans, 989396);
   }
+
+  #[test]
+  fn test_solve() {
+    let s : Sample = "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf"
+      .parse()
+      .expect("shit");
+
+    let wiring = s.solve().expect("shit");
+    let expected : HashMap<char, char> = "abcdefg".chars().zip("cfgabde".chars()).collect();
+    assert_eq!(wiring, expected);
+    assert_eq!(s.decode(), Ok(5353));
+  }
+
+  #[test]
+  fn test_solve_errors() {
+    // only a 1 and a 7: the remaining wires could be in any order
+    let ambiguous : Sample = "ab dab | ab".parse().expect("shit");
+    assert_eq!(ambiguous.solve(), Err("Ambiguous wiring"));
+
+    // two different six wire patterns missing the same segment can't both be digits
+    let contradictory : Sample = "abcdef abcdeg abcdfg abcefg | ab".parse().expect("shit");
+    assert_eq!(contradictory.solve(), Err("No consistent wiring"));
+
+    let unknown : Sample = "xyz | ab".parse().expect("shit");
+    assert_eq!(unknown.solve(), Err("Unknown wire"));
+  }
+
+  #[test]
+  fn test_fonts() {
+    assert!("0 abc".parse::<Font>().is_err());
+    assert!("segments abca\n0 a".parse::<Font>().is_err());
+    assert!("segments abc\n0 ab\n1 ba".parse::<Font>().is_err());
+    assert!("segments abc\n0 abz".parse::<Font>().is_err());
+
+    // a fourteen segment display showing some letters and digits
+    let font : Font = "
+      # a-f outer ring as on a seven segment display, g/h the middle bar halves,
+      # i-k upper diagonals and vertical, l-n lower diagonals and vertical
+      segments abcdefghijklmn
+      A abcefgh
+      C adef
+      E adefg
+      H bcefgh
+      I adjm
+      K efgkn
+      M bcefik
+      N bcefin
+      X ikln
+      Z adkl
+      1 bck
+      7 ajl
+      D abcdjm
+      P abefgh
+      Y ikm
+      L def
+      T ajm
+      J bcde
+      V efil
+      W bcefln
+    ".parse().expect("shit");
+
+    // scramble the wires by shifting every letter along by three
+    let scramble = |word: &str| -> String {
+      word.chars().map(|ch| (b'a' + (ch as u8 - b'a' + 3) % 14) as char).collect()
+    };
+    let samples = font
+      .glyphs
+      .iter()
+      .map(|&(_, shape)| (0..14).filter(|i| shape & 1 << i != 0).map(|i| (b'a' + i) as char).collect::<String>())
+      .map(|w| scramble(&w))
+      .collect::<Vec<_>>()
+      .join(" ");
+    let outputs = ["bcefik", "abcefgh", "adkl", "adefg"].iter().map(|w| scramble(w)).collect::<Vec<_>>().join(" ");
+
+    let s : Sample = format!("{} | {}", samples, outputs).parse().expect("shit");
+    assert_eq!(s.decode_with(&font), Ok(vec!["M", "A", "Z", "E"]));
+    assert_eq!(s.solve_with(&font).expect("shit").get(&'d'), Some(&'a'));
+  }
 }
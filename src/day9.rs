@@ -1,14 +1,31 @@
 use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
+use std::fmt::{Display, Formatter, Result as FmtResult};
 
-const INPUT : &'static str = include_str!("../inputs/day9.txt");
-const SAMPLE : &'static str = include_str!("../inputs/day9.sample.txt");
+const INPUT : &str = include_str!("../inputs/day9.txt");
+const SAMPLE : &str = include_str!("../inputs/day9.sample.txt");
 
 type Point = (isize, isize);
 
+// default height at which cells stop belonging to a basin
+const WALL : isize = 9;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Connectivity {
+  // orthogonal neighbours only
+  Four,
+  // orthogonal and diagonal neighbours
+  Eight
+}
+
 #[derive(Clone)]
 struct Grid {
-  points: HashMap<Point, isize>
+  points: HashMap<Point, isize>,
+  // cells this high or higher separate basins
+  wall: isize,
+  connectivity: Connectivity,
+  // count flat minima (see `low_regions`) as low points
+  plateaus: bool
 }
 
 impl FromStr for Grid {
@@ -31,7 +48,7 @@ impl FromStr for Grid {
       })
       .collect::<Result<HashMap<Point, isize>, Self::Err>>()?;
 
-      Ok(Grid { points })
+      Ok(Grid { points, wall: WALL, connectivity: Connectivity::Four, plateaus: false })
   }
 }
 
@@ -39,16 +56,24 @@ impl Grid {
   fn low_points_score(&self) -> isize {
     self
       .low_points()
+      .iter()
       .filter_map(|pos| self.height_at(pos))
       .map(|h| h + 1)
       .sum()
   }
 
-  fn low_points(&self) -> impl Iterator<Item=&Point> {
+  // low points in reading order.  with plateaus on, each low region
+  // counts once, at its first cell.
+  fn low_points(&self) -> Vec<Point> {
+    if self.plateaus {
+      return self.low_regions().into_iter().map(|cells| cells[0]).collect();
+    }
+
     self
-      .points
-      .keys()
+      .sorted_points()
+      .into_iter()
       .filter(|pos| self.is_low_point(pos))
+      .collect()
   }
 
   fn is_low_point(&self, pos: &Point) -> bool {
@@ -59,8 +84,59 @@ impl Grid {
       .all(|h| height < h)
   }
 
+  // a low region is a connected patch of equal height (possibly a single cell)
+  // whose every neighbour is higher, so flat minima are found too
+  fn low_regions(&self) -> Vec<Vec<Point>> {
+    let mut seen : HashSet<Point> = HashSet::new();
+    let mut regions = vec![];
+
+    for start in self.sorted_points() {
+      if seen.contains(&start) {
+        continue;
+      }
+
+      let height = self.height_at(&start).unwrap();
+      let mut cells = vec![];
+      let mut to_visit = vec![start];
+      let mut lowest = true;
+      seen.insert(start);
+
+      while let Some(pos) = to_visit.pop() {
+        cells.push(pos);
+
+        for np in self.neighbors(&pos) {
+          match self.height_at(&np) {
+            Some(h) if h == height && seen.insert(np) => to_visit.push(np),
+            Some(h) if h < height => lowest = false,
+            _ => {}
+          }
+        }
+      }
+
+      if lowest {
+        cells.sort_by_key(|&(x, y)| (y, x));
+        regions.push(cells);
+      }
+    }
+
+    regions
+  }
+
+  pub fn with_plateaus(mut self, plateaus: bool) -> Self {
+    self.plateaus = plateaus;
+    self
+  }
+
+  pub fn with_connectivity(mut self, connectivity: Connectivity) -> Self {
+    self.connectivity = connectivity;
+    self
+  }
+
   fn neighbors(&self, pos: &Point) -> impl Iterator<Item=Point> {
-    let offsets = &[(-1, 0), (1, 0), (0, -1), (0, 1)];
+    let offsets : &[(isize, isize)] = match self.connectivity {
+      Connectivity::Four => &[(-1, 0), (1, 0), (0, -1), (0, 1)],
+      Connectivity::Eight => &[(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)]
+    };
     let &(x, y) = pos;
     offsets
       .iter()
@@ -68,48 +144,76 @@ impl Grid {
   }
 
   fn height_at(&self, pos: &Point) -> Option<isize> {
-    self.points.get(pos).map(|h| *h)
+    self.points.get(pos).copied()
   }
 
-  fn basin_sizes(&self) -> Vec<usize> {
-    let mut seen : HashSet<Point> = HashSet::new();
-    let mut sizes : Vec<usize> = vec![];
-
-    loop {
-      let mut current : usize = 0;
-      let mut to_visit = vec![];
-
-      let start = self.points
-        .keys()
-        .find(|pos| !seen.contains(pos) && self.height_at(pos).unwrap() != 9);
-
-      match start {
-        None => break,
-        Some(&start_pos) => {
-          to_visit.push(start_pos);
-          seen.insert(start_pos);
-
-          while let Some(pos) = to_visit.pop() {
-            current += 1;
-
-            for np in self.neighbors(&pos) {
-              if !seen.insert(np) {
-                continue;
-              }
-
-              match self.height_at(&np) {
-                Some(h) if h != 9 => { to_visit.push(np); },
-                _ => {}
-              };
-            }
-        }
+  pub fn with_wall(mut self, wall: isize) -> Self {
+    self.wall = wall;
+    self
+  }
+
+  fn is_wall(&self, pos: &Point) -> bool {
+    self.height_at(pos).is_none_or(|h| h >= self.wall)
+  }
+
+  // cells in reading order, so labeling is deterministic
+  fn sorted_points(&self) -> Vec<Point> {
+    let mut points : Vec<Point> = self.points.keys().copied().collect();
+    points.sort_by_key(|&(x, y)| (y, x));
+    points
+  }
+
+  // flood fill every basin in one pass over the grid
+  fn basins(&self) -> BasinMap {
+    let mut labels : HashMap<Point, usize> = HashMap::new();
+    let mut basins : Vec<Basin> = vec![];
+
+    for start in self.sorted_points() {
+      if labels.contains_key(&start) || self.is_wall(&start) {
+        continue;
       }
 
+      let id = basins.len();
+      let mut cells = vec![];
+      let mut to_visit = vec![start];
+      labels.insert(start, id);
+
+      while let Some(pos) = to_visit.pop() {
+        cells.push(pos);
+
+        for np in self.neighbors(&pos) {
+          if !self.is_wall(&np) && !labels.contains_key(&np) {
+            labels.insert(np, id);
+            to_visit.push(np);
+          }
+        }
       }
-      sizes.push(current);
+
+      cells.sort_by_key(|&(x, y)| (y, x));
+      let low_point = *cells
+        .iter()
+        .min_by_key(|&pos| self.height_at(pos))
+        .unwrap();
+
+      basins.push(Basin { id, cells, low_point });
     }
 
-    sizes
+    let (mut min, mut max) = ((0, 0), (-1, -1));
+    if let (Some(x0), Some(x1), Some(y0), Some(y1)) = (
+      self.points.keys().map(|p| p.0).min(),
+      self.points.keys().map(|p| p.0).max(),
+      self.points.keys().map(|p| p.1).min(),
+      self.points.keys().map(|p| p.1).max()
+    ) {
+      min = (x0, y0);
+      max = (x1, y1);
+    }
+
+    BasinMap { basins, labels, min, max }
+  }
+
+  fn basin_sizes(&self) -> Vec<usize> {
+    self.basins().basins.iter().map(|b| b.size()).collect()
   }
 
   fn largest_basins(&self, count: usize) -> Vec<usize> {
@@ -126,6 +230,52 @@ impl Grid {
 }
 
 
+#[derive(Debug, Clone, PartialEq)]
+struct Basin {
+  id: usize,
+  // in reading order
+  cells: Vec<Point>,
+  low_point: Point
+}
+
+impl Basin {
+  pub fn size(&self) -> usize {
+    self.cells.len()
+  }
+}
+
+struct BasinMap {
+  basins: Vec<Basin>,
+  // basin id of every non wall cell
+  labels: HashMap<Point, usize>,
+  min: Point,
+  max: Point
+}
+
+impl BasinMap {
+  pub fn basin_at(&self, pos: &Point) -> Option<&Basin> {
+    self.labels.get(pos).map(|&id| &self.basins[id])
+  }
+}
+
+// one character per basin (cycling through letters and digits), `#` for walls
+impl Display for BasinMap {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    const LABELS : &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+    for y in self.min.1..=self.max.1 {
+      for x in self.min.0..=self.max.0 {
+        match self.labels.get(&(x, y)) {
+          Some(&id) => write!(f, "{}", LABELS[id % LABELS.len()] as char)?,
+          None => write!(f, "#")?
+        }
+      }
+      writeln!(f)?;
+    }
+    Ok(())
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -153,8 +303,71 @@ mod tests {
     let g : Grid = INPUT.parse().expect("shit");
     let b = g.largest_basins(3);
 
-    let ans : usize = b.into_iter().reduce(|a, z| (a * z)).unwrap();
+    let ans : usize = b.into_iter().reduce(|a, z| a * z).unwrap();
 
     assert_eq!(ans, 858494);
   }
+
+  #[test]
+  fn test_basin_map() {
+    let g : Grid = SAMPLE.parse().expect("shit");
+    let map = g.basins();
+
+    assert_eq!(map.basins.len(), 4);
+    assert_eq!(
+      map.basins.iter().map(|b| (b.size(), b.low_point)).collect::<Vec<_>>(),
+      vec![(3, (1, 0)), (9, (9, 0)), (14, (2, 2)), (9, (6, 4))]
+    );
+    assert_eq!(map.basin_at(&(0, 0)).map(|b| b.id), Some(0));
+    assert_eq!(map.basin_at(&(2, 0)), None);
+    assert_eq!(
+      map.to_string(),
+      "aa###bbbbb\n\
+       a#ccc#b#bb\n\
+       #ccccc#d#b\n\
+       ccccc#ddd#\n\
+       #c###ddddd\n"
+    );
+
+    // lowering the wall shrinks basins and can split them up
+    let low = g.with_wall(6).basins();
+    assert_eq!(low.basins.iter().map(|b| b.size()).collect::<Vec<_>>(), vec![3, 9, 1, 1]);
+    assert!(low.basins.iter().all(|b| b.cells.iter().all(|p| low.basin_at(p) == Some(b))));
+  }
+
+  #[test]
+  fn test_low_regions() {
+    let g : Grid = SAMPLE.parse().expect("shit");
+    let strict = g.low_points();
+    assert_eq!(g.low_regions(), strict.iter().map(|&p| vec![p]).collect::<Vec<_>>());
+
+    // a flat valley floor is missed by the strict check
+    let plateau : Grid = "5555\n5115\n5125\n5555".parse().expect("shit");
+    assert!(plateau.low_points().is_empty());
+    assert_eq!(plateau.low_regions(), vec![vec![(1, 1), (2, 1), (1, 2)]]);
+
+    // the two 1s only touch diagonally
+    let diagonal : Grid = "21\n12".parse().expect("shit");
+    assert_eq!(diagonal.low_regions(), vec![vec![(1, 0)], vec![(0, 1)]]);
+    let eight = diagonal.with_connectivity(Connectivity::Eight);
+    assert_eq!(eight.low_regions(), vec![vec![(1, 0), (0, 1)]]);
+    assert!(eight.low_points().is_empty());
+  }
+
+  #[test]
+  fn test_plateau_scoring() {
+    let g : Grid = SAMPLE.parse().expect("shit");
+    assert_eq!(g.with_plateaus(true).low_points_score(), 15);
+
+    // the valley floor at height 1 scores once
+    let plateau : Grid = "5555\n5115\n5125\n5555".parse().expect("shit");
+    assert_eq!(plateau.low_points_score(), 0);
+    let flat = plateau.with_plateaus(true);
+    assert_eq!(flat.low_points(), vec![(1, 1)]);
+    assert_eq!(flat.low_points_score(), 2);
+
+    let diagonal : Grid = "21\n12".parse().expect("shit");
+    assert_eq!(diagonal.clone().with_plateaus(true).low_points_score(), 4);
+    assert_eq!(diagonal.with_connectivity(Connectivity::Eight).with_plateaus(true).low_points_score(), 2);
+  }
 }
@@ -3,6 +3,8 @@ extern crate lazy_static;
 #[macro_use]
 extern crate num_derive;
 #[allow(dead_code)]
+mod automaton;
+#[allow(dead_code)]
 mod day1;
 #[allow(dead_code)]
 mod day2;